@@ -53,8 +53,7 @@
 //!                          XmlItem::as_string(root.clone()));
 //!             }
 //!
-//!             // You may clean memory manually
-//!             XmlItem::clean(root.clone());
+//!             // The whole document is freed, once root goes out of scope.
 //!         }
 //!         Err(val) => {
 //!             // Support error messages
@@ -68,8 +67,14 @@
 //!
 //! ```xml
 //! test.xml starts with "root" element :
-//! <root a="b" c="d">
+//! <!-- some
+//! useful
+//! comment -->
+//! <root a="b" c="_&quot;_">
 //! 	<node_1.1>
+//! 		<!-- some
+//! 			useful
+//! 		comment -->
 //! 		<node_2.1/>
 //! 	</node_1.1>
 //! 	<node_1.2>
@@ -78,8 +83,14 @@
 //! </root>
 //! ----------
 //! Saved result.xml :
-//! <root a="b" c="d">
+//! <!-- some
+//! useful
+//! comment -->
+//! <root a="b" c="_&quot;_">
 //! 	<node_1.1>
+//! 		<!-- some
+//! 			useful
+//! 		comment -->
 //! 		<node_2.1/>
 //! 		<YetAnotherNode ID="42"/>
 //! 	</node_1.1>
@@ -90,8 +101,38 @@
 //! ----------
 //! ```
 //!
+
+// The output above is indented with tabs, just like as_string() writes it.
+#![allow(clippy::tabs_in_doc_comments)]
+// Older code and tests predate clippy and are kept as they are.
+#![allow(clippy::ptr_arg, clippy::needless_borrow)]
+#![cfg_attr(test, allow(clippy::bool_assert_comparison))]
+
+mod xml_attributes;
+mod xml_document;
+mod xml_encoding;
+mod xml_error;
+mod xml_escape;
 mod xml_item;
+mod xml_namespace;
+mod xml_parser_options;
+mod xml_push_parser;
+mod xml_reader;
+mod xml_streamer;
+mod xml_tokenizer;
+mod xml_writer;
+mod xml_writer_options;
 mod xml_dom;
 
-pub use self::xml_item::{XmlItem, XmlItemRc};
-pub use self::xml_dom::XmlDom;
+pub use self::xml_attributes::{XmlAttributes, XmlAttributesIter};
+pub use self::xml_document::{XmlDocument, XmlNodeId};
+pub use self::xml_encoding::XmlEncoding;
+pub use self::xml_error::{XmlError, XmlPosition, XmlSpan};
+pub use self::xml_item::{XmlDeclaration, XmlDoctype, XmlItem, XmlItemKind, XmlItemRc};
+pub use self::xml_namespace::{XmlQName, XMLNS_NAMESPACE, XML_NAMESPACE};
+pub use self::xml_parser_options::ParserOptions;
+pub use self::xml_push_parser::XmlPushParser;
+pub use self::xml_reader::{XmlEvent, XmlReader};
+pub use self::xml_streamer::XmlStreamer;
+pub use self::xml_writer_options::WriterOptions;
+pub use self::xml_dom::{XmlDom, XmlRecovery, XmlSubtreeBuilder};
@@ -0,0 +1,207 @@
+// Licensed to the Square Gears Logic (SGL) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The SGL licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+// http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use std::collections::HashMap;
+use std::iter::FromIterator;
+use std::slice;
+use std::vec;
+
+/// Attributes of an element in the order they were read or set,
+/// so a document is printed the same way every time.
+///
+/// Lookup by name goes through an index, so it's as fast as with a HashMap:
+///
+/// ```
+/// use libsgl_xml::XmlAttributes;
+///
+/// let mut attributes = XmlAttributes::new();
+/// attributes.insert("z".to_string(), "1".to_string());
+/// attributes.insert("a".to_string(), "2".to_string());
+/// attributes.insert("z".to_string(), "3".to_string());
+/// assert_eq!(attributes.get("z"), Some(&"3".to_string()));
+/// assert_eq!(attributes.keys().collect::<Vec<_>>(), vec!["z", "a"]);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct XmlAttributes {
+    entries: Vec<(String, String)>,
+    /// Position of every name in entries.
+    index: HashMap<String, usize>,
+    /// Values, that refer to entities the parser doesn't know, escaped for writing
+    /// with these references kept. Setting a value drops it's escaped one.
+    escaped: HashMap<String, String>,
+}
+
+impl XmlAttributes {
+    pub fn new() -> XmlAttributes {
+        XmlAttributes::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&String> {
+        self.index.get(name).map(|&pos| &self.entries[pos].1)
+    }
+
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.index.contains_key(name)
+    }
+
+    /// Sets value of an attribute and returns the old one. New attributes go last,
+    /// the ones, that are set already, keep their place.
+    pub fn insert(&mut self, name: String, value: String) -> Option<String> {
+        self.escaped.remove(&name);
+        if let Some(&pos) = self.index.get(&name) {
+            return Some(::std::mem::replace(&mut self.entries[pos].1, value));
+        }
+        self.index.insert(name.clone(), self.entries.len());
+        self.entries.push((name, value));
+        None
+    }
+
+    /// Removes an attribute and returns it's value. Attributes after it move one place up.
+    pub fn remove(&mut self, name: &str) -> Option<String> {
+        let pos = self.index.remove(name)?;
+        self.escaped.remove(name);
+        let (_, value) = self.entries.remove(pos);
+        for entry in &self.entries[pos..] {
+            if let Some(moved) = self.index.get_mut(&entry.0) {
+                *moved -= 1;
+            }
+        }
+        Some(value)
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.index.clear();
+        self.escaped.clear();
+    }
+
+    /// Value of an attribute, that refers to entities, which are not predefined,
+    /// escaped the way it's written. Value itself keeps such references as text.
+    pub fn get_escaped(&self, name: &str) -> Option<&String> {
+        self.escaped.get(name)
+    }
+
+    /// Sets the escaped value of an attribute, that is set already.
+    pub fn set_escaped(&mut self, name: &str, escaped: String) {
+        if self.index.contains_key(name) {
+            self.escaped.insert(name.to_string(), escaped);
+        }
+    }
+
+    /// Attributes in their order.
+    pub fn iter(&self) -> XmlAttributesIter<'_> {
+        XmlAttributesIter { entries: self.entries.iter() }
+    }
+
+    pub fn keys<'a>(&'a self) -> impl Iterator<Item = &'a String> + 'a {
+        self.iter().map(|(name, _)| name)
+    }
+
+    pub fn values<'a>(&'a self) -> impl Iterator<Item = &'a String> + 'a {
+        self.iter().map(|(_, value)| value)
+    }
+
+    /// Attributes sorted by name.
+    pub fn sorted(&self) -> Vec<(&String, &String)> {
+        let mut result = self.iter().collect::<Vec<_>>();
+        result.sort_by(|a, b| a.0.cmp(b.0));
+        result
+    }
+}
+
+/// Iterator over `(name, value)` of [XmlAttributes](struct.XmlAttributes.html).
+pub struct XmlAttributesIter<'a> {
+    entries: slice::Iter<'a, (String, String)>,
+}
+
+impl<'a> Iterator for XmlAttributesIter<'a> {
+    type Item = (&'a String, &'a String);
+
+    fn next(&mut self) -> Option<(&'a String, &'a String)> {
+        self.entries.next().map(|entry| (&entry.0, &entry.1))
+    }
+}
+
+impl<'a> IntoIterator for &'a XmlAttributes {
+    type Item = (&'a String, &'a String);
+    type IntoIter = XmlAttributesIter<'a>;
+
+    fn into_iter(self) -> XmlAttributesIter<'a> {
+        self.iter()
+    }
+}
+
+impl IntoIterator for XmlAttributes {
+    type Item = (String, String);
+    type IntoIter = vec::IntoIter<(String, String)>;
+
+    fn into_iter(self) -> vec::IntoIter<(String, String)> {
+        self.entries.into_iter()
+    }
+}
+
+impl FromIterator<(String, String)> for XmlAttributes {
+    fn from_iter<I: IntoIterator<Item = (String, String)>>(iter: I) -> XmlAttributes {
+        let mut result = XmlAttributes::new();
+        for (name, value) in iter {
+            result.insert(name, value);
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::XmlAttributes;
+
+    #[test]
+    fn order() {
+        let mut attributes = ["c", "a", "b"].iter()
+                                            .map(|name| (name.to_string(), name.to_uppercase()))
+                                            .collect::<XmlAttributes>();
+        assert_eq!(attributes.insert("a".to_string(), "1".to_string()),
+                   Some("A".to_string()));
+        assert_eq!(attributes.iter()
+                             .map(|(name, value)| format!("{}={}", name, value))
+                             .collect::<Vec<_>>(),
+                   vec!["c=C", "a=1", "b=B"]);
+        assert_eq!(attributes.sorted()[0], (&"a".to_string(), &"1".to_string()));
+
+        assert_eq!(attributes.remove("c"), Some("C".to_string()));
+        assert_eq!(attributes.remove("c"), None);
+        assert_eq!(attributes.get("b"), Some(&"B".to_string()));
+        assert_eq!(attributes.values().collect::<Vec<_>>(), vec!["1", "B"]);
+        assert!(attributes.contains_key("a"));
+        assert_eq!(attributes.len(), 2);
+
+        attributes.set_escaped("a", "&one;".to_string());
+        attributes.set_escaped("x", "&x;".to_string());
+        assert_eq!(attributes.get_escaped("a"), Some(&"&one;".to_string()));
+        assert_eq!(attributes.get_escaped("x"), None);
+        attributes.insert("a".to_string(), "1".to_string());
+        assert_eq!(attributes.get_escaped("a"), None);
+
+        attributes.insert("c".to_string(), "2".to_string());
+        assert_eq!(attributes.into_iter().last(), Some(("c".to_string(), "2".to_string())));
+    }
+}
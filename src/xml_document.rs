@@ -0,0 +1,619 @@
+// Licensed to the Square Gears Logic (SGL) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The SGL licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+// http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use std::collections::HashMap;
+
+use xml_attributes::XmlAttributes;
+use xml_encoding::XmlEncoding;
+use xml_error::XmlSpan;
+use xml_item::{XmlDeclaration, XmlItem, XmlItemKind, XmlItemRc};
+use xml_writer::{XmlDocumentWriter, XmlWriterNode};
+use xml_writer_options::WriterOptions;
+
+/// Handle of a node in [XmlDocument](struct.XmlDocument.html). It's valid only
+/// for the document, that created it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct XmlNodeId(usize);
+
+/// Node of XmlDocument, same as XmlItem, but with handles instead of references.
+#[derive(Clone, Debug)]
+struct XmlNode {
+    kind: XmlItemKind,
+    name: String,
+    value: String,
+    attributes: XmlAttributes,
+    public_id: Option<String>,
+    system_id: Option<String>,
+    parent: Option<XmlNodeId>,
+    nodes: Vec<XmlNodeId>,
+    span: Option<XmlSpan>,
+    attribute_spans: HashMap<String, XmlSpan>,
+    markup: Option<String>,
+    end_markup: Option<String>,
+}
+
+impl XmlNode {
+    fn new(kind: XmlItemKind, name: String, value: String) -> XmlNode {
+        XmlNode {
+            kind,
+            name,
+            value,
+            attributes: XmlAttributes::new(),
+            public_id: None,
+            system_id: None,
+            parent: None,
+            nodes: vec![],
+            span: None,
+            attribute_spans: HashMap::new(),
+            markup: None,
+            end_markup: None,
+        }
+    }
+
+    fn character_data(&self) -> String {
+        match self.kind {
+            XmlItemKind::EntityReference => format!("&{};", self.name),
+            _ => self.value.clone(),
+        }
+    }
+}
+
+/// Node of a document together with the document, so it can be printed.
+struct XmlNodeView<'a> {
+    document: &'a XmlDocument,
+    id: XmlNodeId,
+}
+
+impl<'a> XmlNodeView<'a> {
+    fn node(&self) -> &'a XmlNode {
+        &self.document.nodes[self.id.0]
+    }
+}
+
+impl<'a> XmlWriterNode for XmlNodeView<'a> {
+    fn kind(&self) -> XmlItemKind {
+        self.node().kind
+    }
+
+    fn name(&self) -> &str {
+        &self.node().name
+    }
+
+    fn value(&self) -> &str {
+        &self.node().value
+    }
+
+    fn attributes(&self) -> &XmlAttributes {
+        &self.node().attributes
+    }
+
+    fn public_id(&self) -> Option<&String> {
+        self.node().public_id.as_ref()
+    }
+
+    fn system_id(&self) -> Option<&String> {
+        self.node().system_id.as_ref()
+    }
+
+    fn markup(&self) -> Option<&String> {
+        self.node().markup.as_ref()
+    }
+
+    fn end_markup(&self) -> Option<&String> {
+        self.node().end_markup.as_ref()
+    }
+
+    fn child_count(&self) -> usize {
+        self.node().nodes.len()
+    }
+
+    fn for_each_child(&self, f: &mut dyn FnMut(&XmlNodeView<'a>)) {
+        for &id in &self.node().nodes {
+            f(&XmlNodeView {
+                document: self.document,
+                id,
+            });
+        }
+    }
+}
+
+/// Document, that owns all of it's nodes and hands out [XmlNodeId](struct.XmlNodeId.html)
+/// handles to them. Nodes are read through `&XmlDocument` and changed through
+/// `&mut XmlDocument`, so there are no reference counters or runtime borrow checks:
+///
+/// ```
+/// use libsgl_xml::{XmlDocument, XmlDom};
+///
+/// let root = XmlDom::parse_str("<list><item id=\"1\"/></list>").unwrap();
+/// let mut document = XmlDocument::from_item(root).unwrap();
+/// let list = document.root();
+/// let item = document.new_element("item".to_string());
+/// document.set_attribute(item, "id".to_string(), "2".to_string());
+/// document.append_child(list, item);
+///
+/// let ids = document.children(list)
+///                   .iter()
+///                   .filter_map(|&id| document.attribute(id, "id"))
+///                   .collect::<Vec<_>>();
+/// assert_eq!(ids, vec!["1", "2"]);
+/// ```
+///
+/// Use [from_item](#method.from_item) and [to_item](#method.to_item) to move between
+/// XmlDocument and [XmlItemRc](type.XmlItemRc.html) trees, e.g. to save a document
+/// with [XmlDom::save_file](struct.XmlDom.html#method.save_file).
+///
+/// Detached nodes stay in the document till it's dropped.
+#[derive(Clone, Debug)]
+pub struct XmlDocument {
+    nodes: Vec<XmlNode>,
+    root: XmlNodeId,
+    declaration: Option<XmlDeclaration>,
+    declaration_markup: Option<String>,
+    prolog: Vec<XmlNodeId>,
+    epilog: Vec<XmlNodeId>,
+}
+
+impl XmlDocument {
+    /// Document with an empty root element.
+    pub fn new(root_name: String) -> XmlDocument {
+        XmlDocument {
+            nodes: vec![XmlNode::new(XmlItemKind::Element, root_name, String::new())],
+            root: XmlNodeId(0),
+            declaration: None,
+            declaration_markup: None,
+            prolog: vec![],
+            epilog: vec![],
+        }
+    }
+
+    /// Copies a tree of XmlItem into a document. The item is treated as the root element,
+    /// so it's declaration, prolog and epilog are copied as well. Returns None for None item.
+    pub fn from_item(rc: XmlItemRc) -> Option<XmlDocument> {
+        let mut result = XmlDocument {
+            nodes: vec![],
+            root: XmlNodeId(0),
+            declaration: None,
+            declaration_markup: None,
+            prolog: vec![],
+            epilog: vec![],
+        };
+        result.root = result.copy_item(&rc, None)?;
+        if let Some(ref xml_item) = *rc.borrow() {
+            result.declaration = xml_item.declaration.clone();
+            result.declaration_markup = xml_item.declaration_markup.clone();
+            for node in &xml_item.prolog {
+                if let Some(id) = result.copy_item(node, None) {
+                    result.prolog.push(id);
+                }
+            }
+            for node in &xml_item.epilog {
+                if let Some(id) = result.copy_item(node, None) {
+                    result.epilog.push(id);
+                }
+            }
+        }
+        Some(result)
+    }
+
+    fn copy_item(&mut self, rc: &XmlItemRc, parent: Option<XmlNodeId>) -> Option<XmlNodeId> {
+        let (node, children) = match *rc.borrow() {
+            Some(ref xml_item) => {
+                (XmlNode {
+                    kind: xml_item.kind,
+                    name: xml_item.name.clone(),
+                    value: xml_item.value.clone(),
+                    attributes: xml_item.attributes.clone(),
+                    public_id: xml_item.public_id.clone(),
+                    system_id: xml_item.system_id.clone(),
+                    parent,
+                    nodes: vec![],
+                    span: xml_item.span,
+                    attribute_spans: xml_item.attribute_spans.clone(),
+                    markup: xml_item.markup.clone(),
+                    end_markup: xml_item.end_markup.clone(),
+                },
+                 xml_item.nodes.clone())
+            }
+            None => return None,
+        };
+        let id = self.push(node);
+        for child in &children {
+            if let Some(child) = self.copy_item(child, Some(id)) {
+                self.nodes[id.0].nodes.push(child);
+            }
+        }
+        Some(id)
+    }
+
+    /// Copies the document into a tree of XmlItem, with declaration, prolog and epilog.
+    pub fn to_item(&self) -> XmlItemRc {
+        let root = self.item(self.root);
+        XmlItem::set_declaration(root.clone(), self.declaration.clone());
+        XmlItem::set_declaration_markup(root.clone(), self.declaration_markup.clone());
+        for &id in &self.prolog {
+            XmlItem::add_prolog_node(root.clone(), self.item(id));
+        }
+        for &id in &self.epilog {
+            XmlItem::add_epilog_node(root.clone(), self.item(id));
+        }
+        root
+    }
+
+    /// Copies a node and it's children into a tree of XmlItem.
+    pub fn item(&self, id: XmlNodeId) -> XmlItemRc {
+        let node = &self.nodes[id.0];
+        let result = XmlItem::new(node.name.clone());
+        if let Some(ref mut xml_item) = *result.borrow_mut() {
+            xml_item.kind = node.kind;
+            xml_item.value = node.value.clone();
+            xml_item.attributes = node.attributes.clone();
+            xml_item.public_id = node.public_id.clone();
+            xml_item.system_id = node.system_id.clone();
+            xml_item.span = node.span;
+            xml_item.attribute_spans = node.attribute_spans.clone();
+            xml_item.markup = node.markup.clone();
+            xml_item.end_markup = node.end_markup.clone();
+        }
+        for &child in &node.nodes {
+            XmlItem::add_node(result.clone(), self.item(child));
+        }
+        result
+    }
+
+    /// Prints the document the same way [XmlItem::as_string](struct.XmlItem.html#method.as_string) does.
+    pub fn as_string(&self) -> String {
+        self.as_string_with(&WriterOptions::default())
+    }
+
+    pub fn as_string_with(&self, options: &WriterOptions) -> String {
+        let mut writer = XmlDocumentWriter::new(options, XmlEncoding::Utf8);
+        writer.declaration(self.declaration.as_ref(), self.declaration_markup.as_ref());
+        for &id in self.prolog.iter().chain(&[self.root]).chain(&self.epilog) {
+            writer.node(&XmlNodeView { document: self, id });
+        }
+        writer.finish()
+    }
+
+    pub fn root(&self) -> XmlNodeId {
+        self.root
+    }
+
+    /// Number of nodes in the document, including detached ones.
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn kind(&self, id: XmlNodeId) -> XmlItemKind {
+        self.nodes[id.0].kind
+    }
+
+    pub fn name(&self, id: XmlNodeId) -> &str {
+        &self.nodes[id.0].name
+    }
+
+    /// Content of a non-element node, empty for elements.
+    pub fn value(&self, id: XmlNodeId) -> &str {
+        &self.nodes[id.0].value
+    }
+
+    /// Same as [XmlItem::get_text](struct.XmlItem.html#method.get_text).
+    pub fn text(&self, id: XmlNodeId) -> String {
+        let node = &self.nodes[id.0];
+        if node.kind != XmlItemKind::Element {
+            return node.character_data();
+        }
+        node.nodes
+            .iter()
+            .filter(|&&child| self.is_character_data(child))
+            .map(|&child| self.nodes[child.0].character_data())
+            .collect()
+    }
+
+    pub fn attribute(&self, id: XmlNodeId, name: &str) -> Option<&str> {
+        self.nodes[id.0].attributes.get(name).map(|value| value.as_str())
+    }
+
+    pub fn attributes(&self, id: XmlNodeId) -> &XmlAttributes {
+        &self.nodes[id.0].attributes
+    }
+
+    pub fn children(&self, id: XmlNodeId) -> &[XmlNodeId] {
+        &self.nodes[id.0].nodes
+    }
+
+    /// None for the root element, nodes of prolog and epilog and detached nodes.
+    pub fn parent(&self, id: XmlNodeId) -> Option<XmlNodeId> {
+        self.nodes[id.0].parent
+    }
+
+    pub fn span(&self, id: XmlNodeId) -> Option<XmlSpan> {
+        self.nodes[id.0].span
+    }
+
+    pub fn declaration(&self) -> Option<&XmlDeclaration> {
+        self.declaration.as_ref()
+    }
+
+    pub fn prolog(&self) -> &[XmlNodeId] {
+        &self.prolog
+    }
+
+    pub fn epilog(&self) -> &[XmlNodeId] {
+        &self.epilog
+    }
+
+    fn is_character_data(&self, id: XmlNodeId) -> bool {
+        matches!(self.nodes[id.0].kind,
+                 XmlItemKind::Text | XmlItemKind::CData | XmlItemKind::EntityReference)
+    }
+
+    fn push(&mut self, node: XmlNode) -> XmlNodeId {
+        self.nodes.push(node);
+        XmlNodeId(self.nodes.len() - 1)
+    }
+
+    /// Creates a detached element. Put it into the tree with append_child().
+    pub fn new_element(&mut self, name: String) -> XmlNodeId {
+        self.push(XmlNode::new(XmlItemKind::Element, name, String::new()))
+    }
+
+    pub fn new_text(&mut self, text: String) -> XmlNodeId {
+        self.push(XmlNode::new(XmlItemKind::Text, String::new(), text))
+    }
+
+    pub fn new_comment(&mut self, text: String) -> XmlNodeId {
+        self.push(XmlNode::new(XmlItemKind::Comment, String::new(), text))
+    }
+
+    pub fn new_cdata(&mut self, text: String) -> XmlNodeId {
+        self.push(XmlNode::new(XmlItemKind::CData, String::new(), text))
+    }
+
+    pub fn new_processing_instruction(&mut self, target: String, data: String) -> XmlNodeId {
+        self.push(XmlNode::new(XmlItemKind::ProcessingInstruction, target, data))
+    }
+
+    /// Moves a node to the end of parent's nodes.
+    ///
+    /// # Panics
+    ///
+    /// If the node is the parent itself or one of it's ancestors.
+    pub fn append_child(&mut self, parent: XmlNodeId, child: XmlNodeId) {
+        self.insert_child(parent, usize::MAX, child);
+    }
+
+    /// Moves a node to the given place among parent's nodes. If the node is
+    /// among them already, the place is counted without it. Index past the end
+    /// puts the node last.
+    ///
+    /// # Panics
+    ///
+    /// If the node is the parent itself or one of it's ancestors.
+    pub fn insert_child(&mut self, parent: XmlNodeId, index: usize, child: XmlNodeId) {
+        let mut ancestor = Some(parent);
+        while let Some(id) = ancestor {
+            assert!(id != child, "node can't be a child of itself");
+            ancestor = self.nodes[id.0].parent;
+        }
+        self.detach(child);
+        let nodes = &mut self.nodes[parent.0].nodes;
+        nodes.insert(index.min(nodes.len()), child);
+        self.nodes[child.0].parent = Some(parent);
+    }
+
+    /// Takes a node out of the tree, prolog or epilog. The node keeps it's own children.
+    pub fn detach(&mut self, id: XmlNodeId) {
+        match self.nodes[id.0].parent.take() {
+            Some(parent) => self.nodes[parent.0].nodes.retain(|&node| node != id),
+            None => {
+                self.prolog.retain(|&node| node != id);
+                self.epilog.retain(|&node| node != id);
+            }
+        }
+    }
+
+    /// Puts a detached node in front of the root element.
+    pub fn add_prolog_node(&mut self, id: XmlNodeId) {
+        self.detach(id);
+        self.prolog.push(id);
+    }
+
+    /// Puts a detached node after the root element.
+    pub fn add_epilog_node(&mut self, id: XmlNodeId) {
+        self.detach(id);
+        self.epilog.push(id);
+    }
+
+    pub fn set_name(&mut self, id: XmlNodeId, name: String) {
+        let node = &mut self.nodes[id.0];
+        node.name = name;
+        node.markup = None;
+        if node.end_markup.as_ref().is_some_and(|markup| !markup.is_empty()) {
+            node.end_markup = Some(format!("</{}>", node.name));
+        }
+    }
+
+    /// Sets value of an attribute and returns the old one.
+    pub fn set_attribute(&mut self, id: XmlNodeId, name: String, value: String) -> Option<String> {
+        let node = &mut self.nodes[id.0];
+        node.markup = None;
+        node.attributes.insert(name, value)
+    }
+
+    pub fn remove_attribute(&mut self, id: XmlNodeId, name: &str) -> Option<String> {
+        let node = &mut self.nodes[id.0];
+        let result = node.attributes.remove(name);
+        if result.is_some() {
+            node.markup = None;
+        }
+        result
+    }
+
+    /// Same as [XmlItem::set_text](struct.XmlItem.html#method.set_text).
+    pub fn set_text(&mut self, id: XmlNodeId, text: String) {
+        if self.nodes[id.0].kind != XmlItemKind::Element {
+            let node = &mut self.nodes[id.0];
+            node.value = text;
+            node.markup = None;
+            return;
+        }
+
+        let position = self.nodes[id.0]
+                           .nodes
+                           .iter()
+                           .position(|&child| self.is_character_data(child))
+                           .unwrap_or(self.nodes[id.0].nodes.len());
+        let old = self.nodes[id.0]
+                      .nodes
+                      .iter()
+                      .cloned()
+                      .filter(|&child| self.is_character_data(child))
+                      .collect::<Vec<_>>();
+        for child in old {
+            self.detach(child);
+        }
+        let text = self.new_text(text);
+        self.insert_child(id, position, text);
+    }
+
+    /// Sets XML declaration. Pass None to print the document without declaration.
+    pub fn set_declaration(&mut self, declaration: Option<XmlDeclaration>) {
+        self.declaration = declaration;
+        self.declaration_markup = None;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::XmlDocument;
+    use xml_dom::XmlDom;
+    use xml_item::{XmlItem, XmlItemKind};
+    use xml_parser_options::ParserOptions;
+    use xml_writer_options::WriterOptions;
+
+    #[test]
+    fn read() {
+        let root = XmlDom::parse_str("<?xml version=\"1.0\"?><!--c--><a x=\"1\">t<b/>u</a>")
+                       .unwrap();
+        let document = XmlDocument::from_item(root.clone()).unwrap();
+        let a = document.root();
+        assert_eq!(document.name(a), "a");
+        assert_eq!(document.attribute(a, "x"), Some("1"));
+        assert_eq!(document.attribute(a, "y"), None);
+        assert_eq!(document.text(a), "tu".to_string());
+        assert_eq!(document.children(a).len(), 3);
+        assert_eq!(document.len(), 5);
+
+        let b = document.children(a)[1];
+        assert_eq!(document.kind(b), XmlItemKind::Element);
+        assert_eq!(document.parent(b), Some(a));
+        assert_eq!(document.parent(a), None);
+        assert_eq!(document.span(b),
+                   XmlItem::get_span(XmlItem::get_nodes(root.clone())[1].clone()));
+
+        assert_eq!(document.declaration().unwrap().version, "1.0".to_string());
+        assert_eq!(document.value(document.prolog()[0]), "c");
+        assert!(document.epilog().is_empty());
+        assert_eq!(document.as_string(), XmlItem::as_string(root));
+
+        assert!(XmlDocument::from_item(XmlItem::get_empty_item()).is_none());
+    }
+
+    #[test]
+    fn change() {
+        let mut document = XmlDocument::new("a".to_string());
+        let a = document.root();
+        let b = document.new_element("b".to_string());
+        let c = document.new_element("c".to_string());
+        document.append_child(a, b);
+        document.append_child(a, c);
+        document.set_attribute(b, "z".to_string(), "1".to_string());
+        document.set_attribute(b, "y".to_string(), "2".to_string());
+        let text = document.new_text("t".to_string());
+        document.insert_child(a, 0, text);
+        assert_eq!(document.as_string(),
+                   "<a>t<b z=\"1\" y=\"2\"/><c/></a>".to_string());
+        assert_eq!(document.as_string_with(&WriterOptions::default().set_sort_attributes(true)),
+                   "<a>t<b y=\"2\" z=\"1\"/><c/></a>".to_string());
+
+        // Moving a node takes it from it's old parent.
+        document.append_child(c, b);
+        assert_eq!(document.children(a).len(), 2);
+        assert_eq!(document.parent(b), Some(c));
+        assert_eq!(document.remove_attribute(b, "z"), Some("1".to_string()));
+
+        // Nodes are moved within the same parent too.
+        let e = document.new_element("e".to_string());
+        document.append_child(a, e);
+        document.append_child(a, text);
+        assert_eq!(document.children(a), &[c, e, text]);
+        document.insert_child(a, 1, c);
+        assert_eq!(document.children(a), &[e, c, text]);
+        document.insert_child(a, 3, text);
+        assert_eq!(document.children(a), &[e, c, text]);
+        document.insert_child(a, 0, text);
+        document.detach(e);
+        assert_eq!(document.children(a), &[text, c]);
+
+        document.set_text(a, "new".to_string());
+        document.set_name(c, "d".to_string());
+        let comment = document.new_comment("e".to_string());
+        document.add_epilog_node(comment);
+        assert_eq!(document.as_string(),
+                   "<a>new<d><b y=\"2\"/></d></a>\n<!--e-->".to_string());
+
+        document.detach(comment);
+        document.detach(b);
+        assert_eq!(document.parent(b), None);
+        assert_eq!(document.as_string(), "<a>new<d/></a>".to_string());
+    }
+
+    #[test]
+    #[should_panic]
+    fn cycles() {
+        let mut document = XmlDocument::new("a".to_string());
+        let b = document.new_element("b".to_string());
+        let root = document.root();
+        document.append_child(root, b);
+        document.append_child(b, root);
+    }
+
+    #[test]
+    fn preserved_formatting() {
+        let text = "<?xml version='1.0'?>\n<a  x='1'>\n  <b/>\n</a>\n";
+        let options = ParserOptions::default().set_preserve_formatting(true);
+        let mut document = XmlDocument::from_item(XmlDom::parse_str_with(text, &options)
+                                                      .unwrap())
+                               .unwrap();
+        assert_eq!(document.as_string(), text.to_string());
+        assert_eq!(XmlItem::as_string(document.to_item()), text.to_string());
+
+        let b = document.children(document.root())[1];
+        document.set_attribute(b, "y".to_string(), "2".to_string());
+        assert_eq!(document.as_string(),
+                   "<?xml version='1.0'?>\n<a  x='1'>\n  <b y=\"2\"/>\n</a>\n".to_string());
+
+        let c = document.new_element("c".to_string());
+        let root = document.root();
+        document.append_child(root, c);
+        assert_eq!(document.as_string(),
+                   "<?xml version='1.0'?>\n<a  x='1'>\n  <b y=\"2\"/>\n  <c/>\n</a>\n".to_string());
+        assert_eq!(XmlItem::as_string(document.to_item()), document.as_string());
+    }
+}
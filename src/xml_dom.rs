@@ -15,10 +15,25 @@
 
 use std::fs::File;
 use std::io::prelude::*;
-use std::io::{BufReader, Error};
+use std::io::{Error, ErrorKind};
+use std::mem;
 use std::path::Path;
 
-use xml_item::{XmlItem, XmlItemRc};
+use xml_encoding::XmlEncoding;
+use xml_error::{XmlError, XmlPosition, XmlSpan};
+use xml_item::{XmlDeclaration, XmlItem, XmlItemRc};
+use xml_parser_options::ParserOptions;
+use xml_reader::{XmlEvent, XmlReader};
+use xml_writer::{self, XmlDocumentWriter};
+use xml_writer_options::WriterOptions;
+
+/// Document read in recovery mode, see [XmlDom::open_recovering](struct.XmlDom.html#method.open_recovering).
+pub struct XmlRecovery {
+    /// The root element, built as far as possible. None if the document has no root element.
+    pub root: Option<XmlItemRc>,
+    /// Problems, that were skipped or repaired, in the order of the document.
+    pub diagnostics: Vec<XmlError>,
+}
 
 /// Represents a bunch of DOM-related algorithms. 
 pub struct XmlDom;
@@ -26,13 +41,11 @@ pub struct XmlDom;
 impl XmlDom {
     /// Reads XML document into browsable DOM structure with single root element.
     ///
-    /// This parser supports:
+    /// Document is read character by character, so layout does not matter:
     ///
     /// ```xml
-    ///  <One/> 
-    ///  <tag/>
-    ///  <per/>
-    ///  <line/>
+    ///  <One/> <tag/>
+    ///  <per/><line/>
     /// ```
     ///
     /// ```xml
@@ -51,197 +64,440 @@ impl XmlDom {
     ///
     /// and multiple attributes, that may contain slashed quoutes \\"
     ///
-    pub fn open(filename: &Path) -> Result<XmlItemRc, String> {
-        let mut result = Err("Can't open xml".to_string());
-        let file = match File::open(filename.as_os_str()) {
-            Ok(file) => file,
-            Err(_) => return result,
-        };
+    /// Slashed quotes, `<--` comments, trimmed text and repeated attributes
+    /// are not standard XML, use [open_with](#method.open_with) with
+    /// [ParserOptions::strict](struct.ParserOptions.html#method.strict) to reject them.
+    ///
+    /// Predefined entities and character references, such as `&amp;` or `&#x263A;`,
+    /// are decoded in attribute values and text. Other entities are declared by DTD,
+    /// which is not read, so references to them are kept and written back as they are.
+    ///
+    /// Namespace prefixes in element and attribute names may be used without
+    /// declaring them, but strict parser wants every prefix to be declared
+    /// by `xmlns:prefix` attribute of the element or one of it's parents.
+    ///
+    /// Any problem is reported as [XmlError](enum.XmlError.html) with position in the file.
+    ///
+    /// Documents, that don't fit into memory, can be read with
+    /// [XmlReader](struct.XmlReader.html) event by event instead.
+    pub fn open(filename: &Path) -> Result<XmlItemRc, XmlError> {
+        Self::open_with(filename, &ParserOptions::default())
+    }
 
-        let mut tag_content = "".to_string();
-        let mut is_tag_begin_found = false;
-        let mut is_tag_end_found = false;
-        let mut is_comment = false;
-        let mut current_parrent = XmlItem::get_empty_item();
-
-        let file = BufReader::new(&file);
-        for line in file.lines() {
-            let line = line.unwrap();
-
-            // Checking closing scope first because in case of comment we can
-            // win time by continuing the loop early.
-            if let &Some(pos) = &line.find(">") {
-                if pos >= 3 && substr_any(&line, &(pos - 2), &pos).eq("-->") {
-                    is_comment = false;
-                    continue;
-                }
-                is_tag_end_found = true;
-            }
+    /// Same as [open](#method.open), but reads the dialect of XML set by options.
+    pub fn open_with(filename: &Path, options: &ParserOptions) -> Result<XmlItemRc, XmlError> {
+        Self::parse_reader_with(File::open(filename.as_os_str())?, options)
+    }
 
-            if let Some(pos) = line.find("<") {
-                if line.chars().count() >= pos + 2 &&
-                   substr_any(&line, &pos, &(pos + 2)).eq("<--") {
-                    is_comment = true;
-                    continue;
-                }
-                is_tag_begin_found = true;
-            }
+    /// Reads XML document without stopping at problems, for editors and the like.
+    /// Malformed tags are skipped, unclosed elements are closed by the close tag
+    /// of their parent or by the end of the document, stray close tags and second
+    /// root elements are dropped. Every problem is reported as a diagnostic
+    /// with it's position:
+    ///
+    /// ```
+    /// use libsgl_xml::{ParserOptions, XmlDom, XmlItem};
+    ///
+    /// let result = XmlDom::parse_str_recovering("<a><b x=1></b><c></a>",
+    ///                                           &ParserOptions::default())
+    ///                  .unwrap();
+    /// assert_eq!(XmlItem::as_string(result.root.unwrap()), "<a>\n\t<c/>\n</a>");
+    /// assert_eq!(result.diagnostics.len(), 3);
+    /// ```
+    ///
+    /// Exceeded limits, I/O and encoding errors still stop the parser and are returned as errors.
+    pub fn open_recovering(filename: &Path,
+                           options: &ParserOptions)
+                           -> Result<XmlRecovery, XmlError> {
+        Self::parse_reader_recovering(File::open(filename.as_os_str())?, options)
+    }
 
-            if is_comment == true {
-                continue;
-            }
+    pub fn parse_reader_recovering<R: Read>(reader: R,
+                                            options: &ParserOptions)
+                                            -> Result<XmlRecovery, XmlError> {
+        let options = options.clone().set_recover(true);
+        Self::build_recovering(XmlReader::with_options(reader, &options), &options)
+    }
 
-            if !line.trim().is_empty() {
-                tag_content = format!("{}\n{}", tag_content, line.trim());
-            }
+    pub fn parse_str_recovering(text: &str,
+                                options: &ParserOptions)
+                                -> Result<XmlRecovery, XmlError> {
+        let options = options.clone().set_recover(true);
+        Self::build_recovering(XmlReader::from_text(text, &options), &options)
+    }
 
-            if is_tag_end_found == false {
-                continue;
-            } else if is_tag_begin_found == false {
-                return Err("There is no matching '<' for '>'".to_string());
+    fn build_recovering<R: Read>(mut reader: XmlReader<R>,
+                                 options: &ParserOptions)
+                                 -> Result<XmlRecovery, XmlError> {
+        let (root, _) = Self::build_document(&mut reader, options)?;
+        Ok(XmlRecovery {
+            root,
+            diagnostics: reader.take_diagnostics(),
+        })
+    }
+
+    /// Reads XML document from any source, such as a socket, the same way
+    /// [open](#method.open) reads a file.
+    pub fn parse_reader<R: Read>(reader: R) -> Result<XmlItemRc, XmlError> {
+        Self::parse_reader_with(reader, &ParserOptions::default())
+    }
+
+    pub fn parse_reader_with<R: Read>(reader: R,
+                                      options: &ParserOptions)
+                                      -> Result<XmlItemRc, XmlError> {
+        Self::build_with(XmlReader::with_options(reader, options), options)
+    }
+
+    /// Reads XML document from bytes in any of [XmlEncoding](enum.XmlEncoding.html)
+    /// encodings. Encoding is detected by byte order mark or XML declaration,
+    /// and is UTF-8 by default.
+    pub fn parse_bytes(bytes: &[u8]) -> Result<XmlItemRc, XmlError> {
+        Self::parse_bytes_with(bytes, &ParserOptions::default())
+    }
+
+    pub fn parse_bytes_with(bytes: &[u8], options: &ParserOptions) -> Result<XmlItemRc, XmlError> {
+        Self::build_with(XmlReader::with_options(bytes, options), options)
+    }
+
+    /// Reads XML document from a string, such as an in-memory template.
+    pub fn parse_str(text: &str) -> Result<XmlItemRc, XmlError> {
+        Self::parse_str_with(text, &ParserOptions::default())
+    }
+
+    pub fn parse_str_with(text: &str, options: &ParserOptions) -> Result<XmlItemRc, XmlError> {
+        Self::build_with(XmlReader::from_text(text, options), options)
+    }
+
+    /// Builds DOM out of events, produced by [XmlReader](struct.XmlReader.html)
+    /// or [XmlPushParser](struct.XmlPushParser.html). All the entry points end up here.
+    pub fn build<I>(events: I) -> Result<XmlItemRc, XmlError>
+        where I: IntoIterator<Item = Result<XmlEvent, XmlError>>
+    {
+        Self::build_with(events, &ParserOptions::default())
+    }
+
+    /// Builds DOM, that has no more nodes than options allow.
+    pub fn build_with<I>(events: I, options: &ParserOptions) -> Result<XmlItemRc, XmlError>
+        where I: IntoIterator<Item = Result<XmlEvent, XmlError>>
+    {
+        let (result, position) = Self::build_document(events, options)?;
+        result.ok_or_else(|| {
+            XmlError::UnexpectedEof {
+                message: "document has no root element".to_string(),
+                position,
             }
+        })
+    }
 
-            if let Some(_) = line.find("</") {
-                let parent = XmlItem::get_parent(current_parrent.clone());
-                if (*parent.borrow_mut()).is_some() {
-                    current_parrent = parent.clone();
-                }
-            } else if let Some(_) = line.find("/>") {
-                match &mut Self::parse_tag(tag_content) {
-                    &mut Ok(ref mut val) => {
-                        XmlItem::add_node(current_parrent.clone(), val.clone());
-                    }
-                    &mut Err(ref mut val) => {
-                        return Err(val.clone());
+    /// Returns the root element, if there is one, and where the document ends.
+    fn build_document<I>(events: I,
+                         options: &ParserOptions)
+                         -> Result<(Option<XmlItemRc>, XmlPosition), XmlError>
+        where I: IntoIterator<Item = Result<XmlEvent, XmlError>>
+    {
+        let mut result: Option<XmlItemRc> = None;
+        let mut declaration = None;
+        let mut prolog = vec![];
+        // Nodes are counted here, including the ones in front of the root element and after it.
+        let mut builder = XmlSubtreeBuilder::with_options(0,
+                                                          &options.clone()
+                                                                  .set_max_nodes(usize::MAX));
+        let mut nodes = 0;
+        let mut position = XmlPosition::default();
+
+        for event in events {
+            let event = event?;
+            match event {
+                XmlEvent::Declaration { .. } |
+                XmlEvent::EndElement { .. } |
+                XmlEvent::EndDocument { .. } => {}
+                _ => {
+                    nodes += 1;
+                    if nodes > options.max_nodes {
+                        return Err(XmlError::LimitExceeded {
+                            limit: "max_nodes",
+                            value: options.max_nodes,
+                            position: event.position(),
+                        });
                     }
                 }
-            } else {
-                match &mut Self::parse_tag(tag_content) {
-                    &mut Ok(ref mut val) => {
-                        if (*current_parrent.borrow_mut()).is_some() {
-                            XmlItem::add_node(current_parrent.clone(), val.clone());
-                        }
-                        current_parrent = val.clone();
-                        if result.is_err() {
-                            result = Ok(current_parrent.clone());
-                        }
+            }
+
+            if builder.is_building() {
+                if let Some(root) = builder.add_event(event)? {
+                    if let Some((value, markup)) = declaration.take() {
+                        XmlItem::set_declaration(root.clone(), Some(value));
+                        XmlItem::set_declaration_markup(root.clone(), markup);
                     }
-                    &mut Err(ref mut val) => {
-                        return Err(val.clone());
+                    for node in prolog.drain(..) {
+                        XmlItem::add_prolog_node(root.clone(), node);
                     }
+                    result = Some(root);
                 }
+                continue;
             }
 
-            is_tag_begin_found = false;
-            is_tag_end_found = false;
-            tag_content = "".to_string();
+            // Comments and processing instructions may appear in front of the root element
+            // or after it, as well as inside of it. So does whitespace, if formatting is preserved.
+            let span = event.span();
+            let (node, markup) = match event {
+                XmlEvent::Declaration { declaration: value, markup, .. } => {
+                    declaration = Some((value, markup));
+                    continue;
+                }
+                XmlEvent::StartElement { .. } => {
+                    builder.add_event(event)?;
+                    continue;
+                }
+                XmlEvent::EndDocument { position: end } => {
+                    position = end;
+                    continue;
+                }
+                XmlEvent::Text { text, markup, .. } => (XmlItem::new_text(text), markup),
+                XmlEvent::Comment { text, markup, .. } => (XmlItem::new_comment(text), markup),
+                XmlEvent::ProcessingInstruction { target, data, markup, .. } => {
+                    (XmlItem::new_processing_instruction(target, data), markup)
+                }
+                XmlEvent::Doctype { doctype, markup, .. } => (XmlItem::new_doctype(doctype), markup),
+                _ => continue,
+            };
+            XmlItem::set_span(node.clone(), Some(span));
+            XmlItem::set_markup(node.clone(), markup);
+            match result {
+                Some(ref root) => {
+                    XmlItem::add_epilog_node(root.clone(), node);
+                }
+                None => prolog.push(node),
+            }
         }
 
-        result
+        Ok((result, position))
     }
 
-    fn parse_tag(tag: String) -> Result<XmlItemRc, String> {
-
-        // TDOD: add Regex from crates.io as a build option.
-        if tag.find("<").is_none() || tag.rfind(">").is_none() {
-            return Err(format!("malformed tag \"{}\"", tag));
-        }
-
-        let tag = tag.replace("\n", " ").trim().to_string();
-        let mut tag_begin = tag.find("<").unwrap() + 1;
-        if let Some(_) = tag.find("</") {
-            tag_begin += 1;
-        }
-
-        let mut tag_end = tag.rfind(">").unwrap() - 1;
-        if let Some(_) = tag.rfind("/>") {
-            tag_end -= 1;
-        }
-
-        let tag = substr_any(&tag, &tag_begin, &tag_end).trim().to_string();
+    /// Writes item into a file the same way [XmlItem::as_string](struct.XmlItem.html#method.as_string)
+    /// prints it. XML declaration of the document is written only if the item has one,
+    /// so set it with [XmlItem::set_declaration](struct.XmlItem.html#method.set_declaration)
+    /// beforehand to get a particular version, encoding or standalone value.
+    ///
+    /// The file is written in the encoding named by the declaration, or in UTF-8
+    /// if there is no declaration or it's encoding is unknown.
+    ///
+    /// Document read with [ParserOptions::preserve_formatting](struct.ParserOptions.html)
+    /// is written as it was read: only nodes changed since then are printed anew.
+    ///
+    /// Nothing is written, if [XmlItem::check](struct.XmlItem.html#method.check) finds
+    /// nodes, that can't be written as XML. The error is of InvalidInput kind then.
+    pub fn save_file(rc: XmlItemRc, filename: &Path) -> Result<(), Error> {
+        Self::save_file_with(rc, filename, &WriterOptions::default())
+    }
 
-        let mut tag_name_end = tag.len() - 1;
-        if let Some(pos) = tag.find(" ") {
-            tag_name_end = pos;
-        }
+    /// Same as [save_file](#method.save_file), but prints the document the way options say,
+    /// e.g. with attributes sorted by name.
+    pub fn save_file_with(rc: XmlItemRc,
+                          filename: &Path,
+                          options: &WriterOptions)
+                          -> Result<(), Error> {
+        let encoding = XmlItem::get_declaration(rc.clone())
+                           .and_then(|declaration| declaration.encoding)
+                           .and_then(|label| XmlEncoding::from_label(&label))
+                           .unwrap_or(XmlEncoding::Utf8);
+        Self::write_file(rc, filename, encoding, None, options)
+    }
 
-        let tag_name = substr_any(&tag, &0, &tag_name_end).trim().to_string();
-        let tag_attributes = substr_any(&tag, &(tag_name_end + 1), &(tag.len() - 1))
-                                 .trim()
-                                 .to_string();
+    /// Writes item into a file in the given encoding. Characters of text and attribute
+    /// values, that the encoding can't represent, are written as character references
+    /// like `&#x263A;`. CDATA sections are split around such characters for that.
+    /// Names, comments, processing instructions and DOCTYPE with such characters can't
+    /// be written: [XmlItem::check_encoded](struct.XmlItem.html#method.check_encoded)
+    /// finds them and nothing is written then.
+    ///
+    /// The file gets XML declaration of the document with the encoding named in it,
+    /// or a new one with version 1.0 if there is none. The item itself is not changed.
+    pub fn save_file_encoded(rc: XmlItemRc,
+                             filename: &Path,
+                             encoding: XmlEncoding)
+                             -> Result<(), Error> {
+        let mut declaration = XmlItem::get_declaration(rc.clone())
+                                  .unwrap_or_else(|| XmlDeclaration::new("1.0".to_string()));
+        declaration.encoding = Some(encoding.name().to_string());
+        Self::write_file(rc, filename, encoding, Some(declaration), &WriterOptions::default())
+    }
 
-        if tag_name.is_empty() {
-            return Err(format!("Can't parse tag \"<{}/>\"", tag));
-        }
-        let result = XmlItem::new(tag_name);
+    /// Writes the document with the declaration, if given, instead of it's own one.
+    fn write_file(rc: XmlItemRc,
+                  filename: &Path,
+                  encoding: XmlEncoding,
+                  declaration: Option<XmlDeclaration>,
+                  options: &WriterOptions)
+                  -> Result<(), Error> {
+        let invalid_input = |error| Error::new(ErrorKind::InvalidInput, error);
+        XmlItem::check_encoded(rc.clone(), encoding).map_err(invalid_input)?;
+        let text = xml_writer::write_item(rc, declaration, XmlDocumentWriter::new(options, encoding));
+        let bytes = encoding.encode(&text).map_err(invalid_input)?;
+        let mut file = File::create(filename.as_os_str())?;
+        file.write_all(&bytes)?;
+        file.sync_all()?;
+        Ok(())
+    }
+}
 
-        if !tag_attributes.is_empty() {
-            Self::parse_attributes(result.clone(), tag_attributes);
-        }
+/// Builds elements of a certain depth out of events, one by one, so a long document
+/// can be processed without keeping all of it in memory. Depth 0 is the root element,
+/// 1 are it's children and so on.
+///
+/// Built elements have no parent, so namespaces declared by the elements around them
+/// are not in scope.
+pub struct XmlSubtreeBuilder {
+    depth: usize,
+    max_nodes: usize,
+    /// How many elements are open at the current event.
+    open_elements: usize,
+    /// The subtree, that is being built. It's elements don't keep their parents alive,
+    /// so the root is kept here.
+    root: XmlItemRc,
+    /// The innermost open element of the subtree, that is being built.
+    current: XmlItemRc,
+    /// Number of nodes in the subtree, that is being built.
+    nodes: usize,
+}
 
-        Ok(result.clone())
+impl XmlSubtreeBuilder {
+    pub fn new(depth: usize) -> XmlSubtreeBuilder {
+        Self::with_options(depth, &ParserOptions::default())
     }
 
-    fn parse_attributes(rc: XmlItemRc, attributes: String) {
-
-        let mut attributes = attributes.trim().to_string();
-        if attributes.is_empty() {
-            return;
+    /// Builder, that limits size of every subtree by `max_nodes` of the options.
+    pub fn with_options(depth: usize, options: &ParserOptions) -> XmlSubtreeBuilder {
+        XmlSubtreeBuilder {
+            depth,
+            max_nodes: options.max_nodes,
+            open_elements: 0,
+            root: XmlItem::get_empty_item(),
+            current: XmlItem::get_empty_item(),
+            nodes: 0,
         }
+    }
 
+    /// Tells whether the last event started a subtree or went inside of one.
+    pub fn is_building(&self) -> bool {
+        self.open_elements > self.depth
+    }
 
-        let mut eq_pos = 0;
-        if let Some(pos) = attributes.find("=") {
-            if pos == 0 {
-                return;
+    /// Adds next event of the document. Returns an element,
+    /// when the event closes it. Events outside of subtrees are skipped.
+    /// End of an element, that was never started, is an error.
+    pub fn add_event(&mut self, event: XmlEvent) -> Result<Option<XmlItemRc>, XmlError> {
+        let adds_node = match event {
+            XmlEvent::StartElement { .. } => self.open_elements >= self.depth,
+            XmlEvent::Text { .. } |
+            XmlEvent::CData { .. } |
+            XmlEvent::EntityReference { .. } |
+            XmlEvent::Comment { .. } |
+            XmlEvent::ProcessingInstruction { .. } => self.is_building(),
+            _ => false,
+        };
+        if adds_node {
+            if !self.is_building() {
+                self.nodes = 0;
             }
-            eq_pos = pos;
-        }
-        let name = substr_any(&attributes, &0, &(eq_pos - 1)).trim().to_string();
-        if attributes.find("\"").is_none() {
-            return;
-        }
-        attributes = substr_any(&attributes,
-                                &(attributes.find("\"").unwrap() + 1),
-                                &(attributes.len() - 1))
-                         .trim()
-                         .to_string();
-
-        let mut is_value_end_found = false;
-        let mut val_end = 0;
-        {
-            let mut iter = attributes.match_indices("\"").filter(|ch| {
-                let val = substr_any(&attributes, &(ch.0 - 1), &ch.0);
-                !val.eq("\\\"")
-            });
-            while let Some(ch) = iter.next() {
-                is_value_end_found = true;
-                val_end = ch.0;
-                break;
+            self.nodes += 1;
+            if self.nodes > self.max_nodes {
+                return Err(XmlError::LimitExceeded {
+                    limit: "max_nodes",
+                    value: self.max_nodes,
+                    position: event.position(),
+                });
             }
         }
 
-        if is_value_end_found {
-            XmlItem::set_attribute(rc.clone(),
-                                   name,
-                                   substr_any(&attributes, &0, &(val_end - 1)));
-        } else {
-            return;
-        }
-        attributes = substr_any(&attributes, &(val_end + 1), &(attributes.len() - 1))
-                         .trim()
-                         .to_string();
-        if !attributes.is_empty() {
-            Self::parse_attributes(rc.clone(), attributes);
+        let span = event.span();
+        match event {
+            XmlEvent::StartElement { name,
+                                     attributes,
+                                     attribute_spans,
+                                     escaped_values,
+                                     markup,
+                                     .. } => {
+                if self.open_elements >= self.depth {
+                    let item = XmlItem::new(name);
+                    XmlItem::set_span(item.clone(), Some(span));
+                    for ((name, value), span) in attributes.into_iter().zip(attribute_spans) {
+                        XmlItem::set_attribute_span(item.clone(), name.clone(), span);
+                        XmlItem::set_attribute(item.clone(), name, value);
+                    }
+                    if let Some(ref mut xml_item) = *item.borrow_mut() {
+                        for (name, escaped) in escaped_values {
+                            xml_item.attributes.set_escaped(&name, escaped);
+                        }
+                    }
+                    XmlItem::set_markup(item.clone(), markup);
+                    if self.is_building() {
+                        XmlItem::add_node(self.current.clone(), item.clone());
+                    } else {
+                        self.root = item.clone();
+                    }
+                    self.current = item;
+                }
+                self.open_elements += 1;
+            }
+            XmlEvent::EndElement { name, markup, .. } => {
+                if self.open_elements == 0 {
+                    return Err(XmlError::UnexpectedCloseTag {
+                        found: name,
+                        position: span.start,
+                    });
+                }
+                self.open_elements -= 1;
+                if self.open_elements >= self.depth {
+                    // Element spans from it's start tag to the end of it's end tag.
+                    let start = XmlItem::get_span(self.current.clone()).unwrap_or(span).start;
+                    XmlItem::set_span(self.current.clone(), Some(XmlSpan::new(start, span.end)));
+                    // Element closed by recovery gets an end tag, if formatting is preserved.
+                    if XmlItem::get_markup(self.current.clone()).is_some() {
+                        XmlItem::set_end_markup(self.current.clone(),
+                                                Some(markup.unwrap_or_else(|| {
+                                                    format!("</{}>", name)
+                                                })));
+                    }
+                }
+                if self.open_elements == self.depth {
+                    self.current = XmlItem::get_empty_item();
+                    return Ok(Some(mem::replace(&mut self.root, XmlItem::get_empty_item())));
+                }
+                if self.is_building() {
+                    self.current = XmlItem::get_parent(self.current.clone());
+                }
+            }
+            XmlEvent::Text { text, markup, .. } if self.is_building() => {
+                let node = XmlItem::add_text(self.current.clone(), text);
+                XmlItem::set_span(node.clone(), Some(span));
+                XmlItem::set_markup(node, markup);
+            }
+            XmlEvent::CData { text, markup, .. } if self.is_building() => {
+                let node = XmlItem::add_node(self.current.clone(), XmlItem::new_cdata(text));
+                XmlItem::set_span(node.clone(), Some(span));
+                XmlItem::set_markup(node, markup);
+            }
+            XmlEvent::EntityReference { name, markup, .. } if self.is_building() => {
+                let node = XmlItem::add_node(self.current.clone(),
+                                             XmlItem::new_entity_reference(name));
+                XmlItem::set_span(node.clone(), Some(span));
+                XmlItem::set_markup(node, markup);
+            }
+            XmlEvent::Comment { text, markup, .. } if self.is_building() => {
+                let node = XmlItem::add_node(self.current.clone(), XmlItem::new_comment(text));
+                XmlItem::set_span(node.clone(), Some(span));
+                XmlItem::set_markup(node, markup);
+            }
+            XmlEvent::ProcessingInstruction { target, data, markup, .. } if self.is_building() => {
+                let node = XmlItem::add_node(self.current.clone(),
+                                             XmlItem::new_processing_instruction(target, data));
+                XmlItem::set_span(node.clone(), Some(span));
+                XmlItem::set_markup(node, markup);
+            }
+            _ => {}
         }
-    }
-
-    pub fn save_file(rc: XmlItemRc, filename: &Path) -> Result<(), Error> {
-
-        let mut file = try!(File::create(filename.as_os_str()));
-        try!(file.write_all(XmlItem::as_string(rc.clone()).as_bytes()));
-        try!(file.sync_all());
-        Ok(())
+        Ok(None)
     }
 }
 
@@ -279,10 +535,21 @@ pub fn substr_try(string: &String, begin: &usize, end: &usize) -> Result<String,
 
 #[cfg(test)]
 mod test {
+    use std::fs::File;
+    use std::io::prelude::*;
+    use std::path::Path;
+
     #[allow(unused_imports)]
-    use xml_item::{XmlItem, XmlItemRc};
+    use xml_item::{XmlDeclaration, XmlItem, XmlItemKind, XmlItemRc};
     #[allow(unused_imports)]
     use XmlDom;
+    use xml_error::{XmlError, XmlPosition, XmlSpan};
+    use xml_encoding::XmlEncoding;
+    use xml_document::XmlDocument;
+    use xml_parser_options::{ParserOptions, MAX_DEPTH_LIMIT};
+    use xml_reader::{XmlEvent, XmlReader};
+    use xml_writer_options::WriterOptions;
+    use super::XmlSubtreeBuilder;
 
     #[test]
     fn substr_any() {
@@ -312,4 +579,606 @@ mod test {
                    super::substr_try(&"abcde".to_string(), &42, &100).is_err());
     }
 
+    #[test]
+    fn parse_minified() {
+        let root = XmlDom::parse_str("<root><a x=\"1>0\"/><b/><c><d/></c></root>").unwrap();
+        let nodes = XmlItem::get_nodes(root.clone());
+        assert_eq!(nodes.len(), 3);
+        assert_eq!(XmlItem::get_name(nodes[1].clone()), "b".to_string());
+        assert_eq!(XmlItem::get_name(XmlItem::get_nodes(nodes[2].clone())[0].clone()),
+                   "d".to_string());
+        let a = nodes[0].borrow();
+        assert_eq!(XmlItem::get_kind(nodes[0].clone()), XmlItemKind::Element);
+        assert_eq!(a.as_ref().unwrap().attributes.get("x"),
+                   Some(&"1>0".to_string()));
+    }
+
+    #[test]
+    fn parse_text() {
+        let root = XmlDom::parse_str("<root>\n  <title>Hello</title>\n  tail\n</root>").unwrap();
+        let nodes = XmlItem::get_nodes(root.clone());
+        assert_eq!(nodes.len(), 2);
+        assert_eq!(XmlItem::get_text(nodes[0].clone()), "Hello".to_string());
+        assert_eq!(XmlItem::get_kind(nodes[1].clone()), XmlItemKind::Text);
+        assert_eq!(XmlItem::get_text(root.clone()), "tail".to_string());
+        assert_eq!(XmlItem::as_string(root.clone()),
+                   "<root><title>Hello</title>tail</root>".to_string());
+    }
+
+    #[test]
+    fn parse_mixed_content() {
+        let text = "<p>Hello <b>world</b>!</p>";
+        let root = XmlDom::parse_str_with(text, &ParserOptions::strict()).unwrap();
+        assert_eq!(XmlItem::as_string(root), text.to_string());
+
+        for options in &[ParserOptions::lenient(), ParserOptions::strict()] {
+            for text in &[text, "<a>\n  <b/>\n</a>", "<a>\n\t<p>x <i>y<j/></i></p>\n\t<c/>\n</a>"] {
+                let once = XmlItem::as_string(XmlDom::parse_str_with(text, options).unwrap());
+                let twice = XmlItem::as_string(XmlDom::parse_str_with(&once, options).unwrap());
+                assert_eq!(once, twice);
+            }
+        }
+        let root = XmlDom::parse_str_with("<a>\n  <b/>\n</a>", &ParserOptions::strict()).unwrap();
+        assert_eq!(XmlItem::as_string(root), "<a>\n  <b/>\n</a>".to_string());
+    }
+
+    #[test]
+    fn parse_comments() {
+        let text = "<!-- head -->\n<root>\n\t<!-- <a/>\n\t-->\n\t<-- legacy -->\n</root>\n<!-- tail -->";
+        let root = XmlDom::parse_str(text).unwrap();
+        let nodes = XmlItem::get_nodes(root.clone());
+        assert_eq!(nodes.len(), 2);
+        assert_eq!(XmlItem::get_kind(nodes[0].clone()), XmlItemKind::Comment);
+        assert_eq!(XmlItem::get_text(nodes[0].clone()), " <a/>\n\t".to_string());
+        assert_eq!(XmlItem::get_prolog(root.clone()).len(), 1);
+        assert_eq!(XmlItem::get_epilog(root.clone()).len(), 1);
+        assert_eq!(XmlItem::as_string(root.clone()),
+                   "<!-- head -->\n<root>\n\t<!-- <a/>\n\t-->\n\t<!-- legacy -->\n</root>\n<!-- tail -->"
+                       .to_string());
+    }
+
+    #[test]
+    fn parse_cdata() {
+        let text = "<sql>\n  <![CDATA[SELECT *\n  FROM t WHERE a < 1 && b > 2]]>\n</sql>";
+        let root = XmlDom::parse_str(text).unwrap();
+        let nodes = XmlItem::get_nodes(root.clone());
+        assert_eq!(nodes.len(), 1);
+        assert_eq!(XmlItem::get_kind(nodes[0].clone()), XmlItemKind::CData);
+        assert_eq!(XmlItem::get_text(root.clone()),
+                   "SELECT *\n  FROM t WHERE a < 1 && b > 2".to_string());
+        assert_eq!(XmlItem::as_string(root.clone()),
+                   "<sql><![CDATA[SELECT *\n  FROM t WHERE a < 1 && b > 2]]></sql>".to_string());
+    }
+
+    #[test]
+    fn parse_declaration() {
+        let root = XmlDom::parse_str("<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"no\"?>\n<a/>")
+                       .unwrap();
+        let declaration = XmlItem::get_declaration(root.clone()).unwrap();
+        assert_eq!(declaration.version, "1.0".to_string());
+        assert_eq!(declaration.encoding, Some("UTF-8".to_string()));
+        assert_eq!(declaration.standalone, Some(false));
+        assert_eq!(XmlItem::as_string(root.clone()),
+                   "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"no\"?>\n<a/>".to_string());
+
+        let root = XmlDom::parse_str("<a/>").unwrap();
+        assert_eq!(XmlItem::get_declaration(root.clone()), None);
+        XmlItem::set_declaration(root.clone(), Some(XmlDeclaration::new("1.1".to_string())));
+        assert_eq!(XmlItem::as_string(root.clone()),
+                   "<?xml version=\"1.1\"?>\n<a/>".to_string());
+
+        assert!(XmlDom::parse_str("<!-- a --><?xml version=\"1.0\"?><a/>").is_err());
+        assert!(XmlDom::parse_str("<?xml encoding=\"UTF-8\"?><a/>").is_err());
+        assert!(XmlDom::parse_str("<?xml version=\"1.0\" standalone=\"maybe\"?><a/>").is_err());
+    }
+
+    #[test]
+    fn parse_processing_instructions() {
+        let text = "<?xml version=\"1.0\"?>\n<?xml-stylesheet href=\"a.xsl\"?>\n\
+                    <root><?tool run?></root>\n<?done?>";
+        let root = XmlDom::parse_str(text).unwrap();
+        let prolog = XmlItem::get_prolog(root.clone());
+        assert_eq!(prolog.len(), 1);
+        assert_eq!(XmlItem::get_kind(prolog[0].clone()),
+                   XmlItemKind::ProcessingInstruction);
+        assert_eq!(XmlItem::get_name(prolog[0].clone()), "xml-stylesheet".to_string());
+        assert_eq!(XmlItem::get_text(prolog[0].clone()), "href=\"a.xsl\"".to_string());
+        assert_eq!(XmlItem::as_string(root.clone()),
+                   "<?xml version=\"1.0\"?>\n<?xml-stylesheet href=\"a.xsl\"?>\n\
+                    <root>\n\t<?tool run?>\n</root>\n<?done?>"
+                       .to_string());
+    }
+
+    #[test]
+    fn parse_doctype() {
+        let text = "<?xml version=\"1.0\"?>\n<!-- head -->\n\
+                    <!DOCTYPE root SYSTEM \"x.dtd\" [\n  <!ELEMENT root EMPTY>\n]>\n<root/>";
+        let root = XmlDom::parse_str(text).unwrap();
+        let doctype = XmlItem::get_doctype(root.clone()).unwrap();
+        assert_eq!(doctype.name, "root".to_string());
+        assert_eq!(doctype.public_id, None);
+        assert_eq!(doctype.system_id, Some("x.dtd".to_string()));
+        assert_eq!(doctype.internal_subset,
+                   Some("\n  <!ELEMENT root EMPTY>\n".to_string()));
+        assert_eq!(XmlItem::as_string(root.clone()), text.to_string());
+
+        assert!(XmlDom::parse_str("<!DOCTYPE a><!DOCTYPE a><a/>").is_err());
+        assert!(XmlDom::parse_str("<a><!DOCTYPE a></a>").is_err());
+    }
+
+    #[test]
+    fn parse_entities() {
+        let text = "<a b=\"&co; &amp;\">x &co; y&amp;</a>";
+        let root = XmlDom::parse_str(text).unwrap();
+        let nodes = XmlItem::get_nodes(root.clone());
+        assert_eq!(nodes.len(), 3);
+        assert_eq!(XmlItem::get_kind(nodes[1].clone()), XmlItemKind::EntityReference);
+        assert_eq!(XmlItem::get_name(nodes[1].clone()), "co".to_string());
+        assert_eq!(XmlItem::get_text(root.clone()), "x &co; y&".to_string());
+        assert_eq!(XmlItem::as_string(root.clone()),
+                   "<a b=\"&co; &amp;\">x &co; y&amp;</a>".to_string());
+
+        // Strict parser wants entities to be declared.
+        let strict = ParserOptions::strict();
+        assert!(XmlDom::parse_str_with(text, &strict).is_err());
+        assert!(XmlDom::parse_str_with("<a b=\"&co;\"/>", &strict).is_err());
+        let declared = "<!DOCTYPE a [<!ENTITY co \"Company\">]><a b=\"&co;\">&co;</a>";
+        assert!(XmlDom::parse_str_with(declared, &strict).is_ok());
+        let external = "<!DOCTYPE a SYSTEM \"a.dtd\"><a>&co;</a>";
+        assert!(XmlDom::parse_str_with(external, &strict).is_ok());
+    }
+
+    #[test]
+    fn parse_namespaces() {
+        let text = "<soap:Envelope xmlns:soap=\"http://www.w3.org/2003/05/soap-envelope\">\
+                    <soap:Body xmlns=\"urn:app\"><Order id=\"1\"/></soap:Body></soap:Envelope>";
+        let root = XmlDom::parse_str(text).unwrap();
+        let body = XmlItem::get_nodes(root.clone())[0].clone();
+        let order = XmlItem::get_nodes(body.clone())[0].clone();
+        assert_eq!(XmlItem::get_prefix(body.clone()), Some("soap".to_string()));
+        assert_eq!(XmlItem::get_local_name(body.clone()), "Body".to_string());
+        assert_eq!(XmlItem::get_namespace_uri(body.clone()),
+                   Some("http://www.w3.org/2003/05/soap-envelope".to_string()));
+        assert_eq!(XmlItem::get_namespace_uri(order.clone()), Some("urn:app".to_string()));
+
+        let strict = ParserOptions::strict();
+        assert!(XmlDom::parse_str_with("<a:b/>", &strict).is_err());
+        assert!(XmlDom::parse_str_with("<a xmlns:p=\"urn:p\"><b p:c=\"1\" q:d=\"2\"/></a>",
+                                       &strict)
+                    .is_err());
+        assert!(XmlDom::parse_str_with("<:a/>", &strict).is_err());
+        assert!(XmlDom::parse_str_with("<a:b:c xmlns:a=\"urn:a\"/>", &strict).is_err());
+
+        // Old SglXml files use prefixes without declaring them.
+        let root = XmlDom::parse_str("<soap:a><b q:d=\"2\"/></soap:a>").unwrap();
+        assert_eq!(XmlItem::get_prefix(root.clone()), Some("soap".to_string()));
+        assert_eq!(XmlItem::get_namespace_uri(root.clone()), None);
+        assert_eq!(XmlItem::as_string(root.clone()),
+                   "<soap:a>\n\t<b q:d=\"2\"/>\n</soap:a>".to_string());
+    }
+
+    #[test]
+    fn error_positions() {
+        match XmlDom::parse_str_with("<a>\n  <b/>\n  <c:d/>\n</a>", &ParserOptions::strict()) {
+            Err(XmlError::Syntax { position, .. }) => {
+                assert_eq!(position, XmlPosition::new(3, 3, 13))
+            }
+            _ => panic!(),
+        }
+        match XmlDom::parse_str("<!-- a -->\n") {
+            Err(XmlError::UnexpectedEof { position, .. }) => {
+                assert_eq!(position, XmlPosition::new(2, 1, 11))
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(XmlDom::parse_str("").is_err());
+        assert!(XmlDom::parse_str("<root><a x=1/></root>").is_err());
+        assert!(XmlDom::parse_str("<root/><root/>").is_err());
+    }
+
+    #[test]
+    fn text_outside_root() {
+        let strict = ParserOptions::strict();
+        let syntax_error = |text: &str| match XmlDom::parse_str_with(text, &strict) {
+            Err(XmlError::Syntax { position, .. }) => position,
+            _ => panic!(),
+        };
+        assert_eq!(syntax_error("junk<a/>"), XmlPosition::new(1, 1, 0));
+        assert_eq!(syntax_error("<a/>\nmore"), XmlPosition::new(1, 5, 4));
+        assert_eq!(syntax_error("<a/><![CDATA[x]]>"), XmlPosition::new(1, 5, 4));
+        assert_eq!(syntax_error("&x;<a/>"), XmlPosition::new(1, 1, 0));
+        assert!(XmlDom::parse_str_with(" \n<a/>\n ", &strict).is_ok());
+
+        let result = XmlDom::parse_str_recovering("junk<a/>more", &strict).unwrap();
+        assert_eq!(XmlItem::as_string(result.root.unwrap()), "<a/>".to_string());
+        assert_eq!(result.diagnostics.len(), 2);
+
+        // Old SglXml files are read as before.
+        let root = XmlDom::parse_str("junk<a/>more").unwrap();
+        assert_eq!(XmlItem::as_string(root), "<a/>".to_string());
+    }
+
+    #[test]
+    fn parse_sources() {
+        let text = "<a>\u{263A}</a>";
+        let from_str = XmlDom::parse_str(text).unwrap();
+        let from_bytes = XmlDom::parse_bytes(text.as_bytes()).unwrap();
+        let from_reader = XmlDom::parse_reader(text.as_bytes()).unwrap();
+        assert_eq!(XmlItem::as_string(from_bytes), XmlItem::as_string(from_str.clone()));
+        assert_eq!(XmlItem::as_string(from_reader), XmlItem::as_string(from_str));
+
+        match XmlDom::parse_bytes(b"<a>\n\xFF</a>") {
+            Err(XmlError::Encoding { position, .. }) => {
+                assert_eq!(position, XmlPosition::new(2, 1, 4))
+            }
+            _ => panic!(),
+        }
+        match XmlDom::open(Path::new("./tests/no_such_file.xml")) {
+            Err(XmlError::Io { .. }) => {}
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn parse_options() {
+        let text = "<a x=\"\\\"\"> b <--c--></a>";
+        let root = XmlDom::parse_str(text).unwrap();
+        assert_eq!(XmlItem::get_text(root.clone()), "b".to_string());
+        assert_eq!(XmlItem::as_string(root),
+                   "<a x=\"&quot;\">b<!--c--></a>".to_string());
+
+        assert!(XmlDom::parse_str_with(text, &ParserOptions::strict()).is_err());
+        let options = ParserOptions::strict().set_legacy_comments(true);
+        assert!(XmlDom::parse_str_with(text, &options).is_err());
+        let options = options.set_backslash_quotes(true);
+        let root = XmlDom::parse_str_with(text, &options).unwrap();
+        assert_eq!(XmlItem::get_text(root), " b ".to_string());
+
+        assert!(XmlDom::parse_str_with("<a x='1' x='2'/>", &ParserOptions::strict()).is_err());
+        assert_eq!(ParserOptions::default(), ParserOptions::lenient());
+    }
+
+    #[test]
+    fn parse_encodings() {
+        let root = XmlDom::parse_bytes(b"<?xml version=\"1.0\" encoding=\"ISO-8859-1\"?>\
+                                         <a>caf\xE9</a>")
+                       .unwrap();
+        assert_eq!(XmlItem::get_text(root.clone()), "caf\u{E9}".to_string());
+        assert_eq!(XmlItem::get_declaration(root).unwrap().encoding,
+                   Some("ISO-8859-1".to_string()));
+
+        let mut utf16 = vec![0xFE, 0xFF];
+        for unit in "<a>\u{263A}</a>".encode_utf16() {
+            utf16.extend_from_slice(&unit.to_be_bytes());
+        }
+        let root = XmlDom::parse_bytes(&utf16).unwrap();
+        assert_eq!(XmlItem::get_text(root), "\u{263A}".to_string());
+
+        match XmlDom::parse_bytes(b"<?xml version='1.0' encoding='EBCDIC'?><a/>") {
+            Err(XmlError::Encoding { .. }) => {}
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn save_encodings() {
+        let root = XmlDom::parse_str("<a b=\"\u{263A}\">caf\u{E9}</a>").unwrap();
+        let filename = ::std::env::temp_dir().join("libsgl_xml_save_encodings.xml");
+
+        XmlDom::save_file_encoded(root.clone(), &filename, XmlEncoding::Iso8859_1).unwrap();
+        let mut bytes = vec![];
+        File::open(&filename).unwrap().read_to_end(&mut bytes).unwrap();
+        assert_eq!(bytes,
+                   b"<?xml version=\"1.0\" encoding=\"ISO-8859-1\"?>\n\
+                     <a b=\"&#x263A;\">caf\xE9</a>"
+                       .to_vec());
+        let loaded = XmlDom::open(&filename).unwrap();
+        assert!(XmlItem::as_string(loaded).ends_with(&XmlItem::as_string(root.clone())));
+        // The document itself keeps it's declaration.
+        assert!(XmlItem::get_declaration(root.clone()).is_none());
+
+        // CDATA sections are split around characters, that the encoding can't represent.
+        let cdata = XmlDom::parse_str("<a><![CDATA[smile \u{263A}!]]></a>").unwrap();
+        XmlDom::save_file_encoded(cdata.clone(), &filename, XmlEncoding::Iso8859_1).unwrap();
+        let mut saved = String::new();
+        File::open(&filename).unwrap().read_to_string(&mut saved).unwrap();
+        assert!(saved.ends_with("<a><![CDATA[smile ]]>&#x263A;<![CDATA[!]]></a>"));
+        assert_eq!(XmlItem::get_text(XmlDom::open(&filename).unwrap()),
+                   "smile \u{263A}!".to_string());
+
+        // Names and comments can't hold character references.
+        for text in &["<a><!-- \u{263A} --></a>", "<\u{263A}/>", "<a \u{E9}='1'/>"] {
+            let item = XmlDom::parse_str(text).unwrap();
+            assert_eq!(XmlDom::save_file_encoded(item, &filename, XmlEncoding::Ascii)
+                           .unwrap_err()
+                           .kind(),
+                       ::std::io::ErrorKind::InvalidInput);
+        }
+
+        // US-ASCII declared by the document is kept.
+        let ascii = XmlDom::parse_str("<?xml version='1.0' encoding='US-ASCII'?><a>\u{E9}</a>")
+                        .unwrap();
+        XmlDom::save_file(ascii, &filename).unwrap();
+        let mut bytes = vec![];
+        File::open(&filename).unwrap().read_to_end(&mut bytes).unwrap();
+        assert!(bytes.ends_with(b"<a>&#xE9;</a>"));
+
+        XmlDom::save_file_encoded(root.clone(), &filename, XmlEncoding::Utf16Le).unwrap();
+        let loaded = XmlDom::open(&filename).unwrap();
+        assert_eq!(XmlItem::get_declaration(loaded.clone()).unwrap().encoding,
+                   Some("UTF-16".to_string()));
+        assert_eq!(XmlItem::get_text(loaded), "caf\u{E9}".to_string());
+
+        // Plain save keeps the encoding, the document declares.
+        XmlDom::save_file(root.clone(), &filename).unwrap();
+        assert!(XmlDom::open(&filename).is_ok());
+
+        // Nodes, that can't be written as XML, are not.
+        XmlItem::add_node(root.clone(), XmlItem::new_comment("a--b".to_string()));
+        assert_eq!(XmlDom::save_file(root.clone(), &filename).unwrap_err().kind(),
+                   ::std::io::ErrorKind::InvalidInput);
+        ::std::fs::remove_file(&filename).unwrap();
+    }
+
+    #[test]
+    fn parse_limits() {
+        let limit = |result: Result<XmlItemRc, XmlError>| {
+            match result {
+                Err(XmlError::LimitExceeded { limit, .. }) => limit,
+                _ => panic!(),
+            }
+        };
+
+        let deep = "<a>".repeat(100_000);
+        assert_eq!(limit(XmlDom::parse_str(&deep)), "max_depth");
+        let options = ParserOptions::default().set_max_depth(3);
+        assert!(XmlDom::parse_str_with("<a><b><c/></b></a>", &options).is_ok());
+        assert_eq!(limit(XmlDom::parse_str_with("<a><b><c><d/></c></b></a>", &options)),
+                   "max_depth");
+
+        // The deepest tree allowed is written, checked, copied and dropped
+        // within the stack of a test thread.
+        let options = ParserOptions::default().set_max_depth(usize::MAX);
+        assert_eq!(options.max_depth, MAX_DEPTH_LIMIT);
+        let deep = format!("{}{}", "<a>".repeat(MAX_DEPTH_LIMIT), "</a>".repeat(MAX_DEPTH_LIMIT));
+        let root = XmlDom::parse_str_with(&deep, &options).unwrap();
+        assert!(XmlItem::as_string(root.clone()).ends_with("\t</a>\n</a>"));
+        assert!(XmlItem::check(root.clone()).is_ok());
+        assert!(XmlDocument::from_item(root).unwrap().as_string().ends_with("\t</a>\n</a>"));
+        let options = ParserOptions { max_depth: usize::MAX, ..ParserOptions::default() };
+        let deeper = format!("<a>{}</a>", deep);
+        match XmlDom::parse_str_with(&deeper, &options) {
+            Err(XmlError::LimitExceeded { value, .. }) => assert_eq!(value, MAX_DEPTH_LIMIT),
+            _ => panic!(),
+        }
+
+        let options = ParserOptions::default()
+                          .set_max_attributes(2)
+                          .set_max_name_length(4)
+                          .set_max_text_length(5)
+                          .set_max_nodes(4);
+        assert!(XmlDom::parse_str_with("<a x='1' y='2'>12345</a>", &options).is_ok());
+        assert_eq!(limit(XmlDom::parse_str_with("<a x='1' y='2' z='3'/>", &options)),
+                   "max_attributes");
+        assert_eq!(limit(XmlDom::parse_str_with("<abcde/>", &options)), "max_name_length");
+        assert_eq!(limit(XmlDom::parse_str_with("<a>123456</a>", &options)),
+                   "max_text_length");
+        assert_eq!(limit(XmlDom::parse_str_with("<a x='123456'/>", &options)),
+                   "max_text_length");
+        assert_eq!(limit(XmlDom::parse_str_with("<!--123456--><a/>", &options)),
+                   "max_text_length");
+        assert!(XmlDom::parse_str_with("<!--1--><a><b/>2</a>", &options).is_ok());
+        match XmlDom::parse_str_with("<!--1--><a><b/>2</a><?pi?>", &options) {
+            Err(XmlError::LimitExceeded { limit, value, position }) => {
+                assert_eq!((limit, value), ("max_nodes", 4));
+                assert_eq!(position, XmlPosition::new(1, 21, 20));
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn subtree_limits() {
+        let options = ParserOptions::default().set_max_nodes(2);
+        let mut builder = XmlSubtreeBuilder::with_options(1, &options);
+        let mut count = 0;
+        for event in XmlReader::from_text("<a><b>1</b><b>2</b><b>3<c/></b></a>", &options) {
+            match builder.add_event(event.unwrap()) {
+                Ok(Some(_)) => count += 1,
+                Ok(None) => {}
+                Err(XmlError::LimitExceeded { .. }) => break,
+                Err(_) => panic!(),
+            }
+        }
+        assert_eq!(count, 2);
+
+        let mut builder = XmlSubtreeBuilder::new(0);
+        let event = XmlEvent::EndElement {
+            name: "a".to_string(),
+            span: XmlSpan::new(XmlPosition::new(1, 5, 4), XmlPosition::new(1, 9, 8)),
+            markup: None,
+        };
+        match builder.add_event(event) {
+            Err(XmlError::UnexpectedCloseTag { found, position }) => {
+                assert_eq!(found, "a".to_string());
+                assert_eq!(position, XmlPosition::new(1, 5, 4));
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn parse_close_tags() {
+        match XmlDom::parse_str("<a><b></a></b>") {
+            Err(XmlError::MismatchedCloseTag { expected, found, position }) => {
+                assert_eq!(expected, "b".to_string());
+                assert_eq!(found, "a".to_string());
+                assert_eq!(position, XmlPosition::new(1, 7, 6));
+            }
+            _ => panic!(),
+        }
+        match XmlDom::parse_str("<a/></a>") {
+            Err(XmlError::UnexpectedCloseTag { found, position }) => {
+                assert_eq!(found, "a".to_string());
+                assert_eq!(position, XmlPosition::new(1, 5, 4));
+            }
+            _ => panic!(),
+        }
+        match XmlDom::parse_str("<a>\n<b>\n</b>\n") {
+            Err(XmlError::UnexpectedEof { message, position }) => {
+                assert_eq!(message,
+                           "element \"a\" opened at line 1, column 1 is not closed".to_string());
+                assert_eq!(position, XmlPosition::new(4, 1, 13));
+            }
+            _ => panic!(),
+        }
+        assert!(XmlDom::parse_str("<a:b xmlns:a=\"urn:a\"></a:b>").is_ok());
+    }
+
+    #[test]
+    fn parse_frees_memory() {
+        let root = XmlDom::parse_str("<a><b><c>text</c></b></a>").unwrap();
+        let b = XmlItem::get_nodes(root.clone())[0].clone();
+        let c = ::std::rc::Rc::downgrade(&XmlItem::get_nodes(b.clone())[0]);
+        assert_eq!(XmlItem::get_name(XmlItem::get_parent(b.clone())), "a".to_string());
+        drop(root);
+        assert!(XmlItem::get_parent(b.clone()).borrow().is_none());
+        drop(b);
+        assert!(c.upgrade().is_none());
+    }
+
+    #[test]
+    fn parse_spans() {
+        let root = XmlDom::parse_str("<!--c-->\n<a x=\"1\">\n  <b y='2'/>text\n</a>").unwrap();
+        assert_eq!(XmlItem::get_span(root.clone()),
+                   Some(XmlSpan::new(XmlPosition::new(2, 1, 9), XmlPosition::new(4, 5, 40))));
+        assert_eq!(XmlItem::get_attribute_span(root.clone(), "x"),
+                   Some(XmlSpan::new(XmlPosition::new(2, 4, 12), XmlPosition::new(2, 9, 17))));
+
+        let nodes = XmlItem::get_nodes(root.clone());
+        assert_eq!(XmlItem::get_span(nodes[0].clone()),
+                   Some(XmlSpan::new(XmlPosition::new(3, 3, 21), XmlPosition::new(3, 13, 31))));
+        assert_eq!(XmlItem::get_attribute_span(nodes[0].clone(), "y"),
+                   Some(XmlSpan::new(XmlPosition::new(3, 6, 24), XmlPosition::new(3, 11, 29))));
+        assert_eq!(XmlItem::get_span(nodes[1].clone()),
+                   Some(XmlSpan::new(XmlPosition::new(3, 13, 31), XmlPosition::new(4, 1, 36))));
+
+        let prolog = XmlItem::get_prolog(root.clone());
+        assert_eq!(XmlItem::get_span(prolog[0].clone()),
+                   Some(XmlSpan::new(XmlPosition::new(1, 1, 0), XmlPosition::new(1, 9, 8))));
+    }
+
+    #[test]
+    fn parse_recovering() {
+        let options = ParserOptions::default().set_unbound_prefixes(false);
+        let text = "<!DOCTYPE a>\n<a>\n<x:b>1</x:b><c>2<d>3\n</a><?xml version='1.0'?>";
+        let result = XmlDom::parse_str_recovering(text, &options).unwrap();
+        let root = result.root.unwrap();
+        assert_eq!(XmlItem::as_string(root.clone()),
+                   "<!DOCTYPE a>\n<a>\n\t<x:b>1</x:b>\n\t<c>2<d>3</d></c>\n</a>"
+                       .to_string());
+        let positions = result.diagnostics
+                              .iter()
+                              .map(|diagnostic| diagnostic.position())
+                              .collect::<Vec<_>>();
+        assert_eq!(positions,
+                   vec![XmlPosition::new(3, 1, 17),
+                        XmlPosition::new(4, 1, 38),
+                        XmlPosition::new(4, 5, 42)]);
+
+        // The tree is repaired, so it's spans are still there.
+        let c = XmlItem::get_nodes(root.clone())[1].clone();
+        assert_eq!(XmlItem::get_span(c),
+                   Some(XmlSpan::new(XmlPosition::new(3, 13, 29), XmlPosition::new(4, 1, 38))));
+
+        let result = XmlDom::parse_str_recovering("<!-- nothing -->", &options).unwrap();
+        assert!(result.root.is_none());
+        assert_eq!(result.diagnostics.len(), 1);
+
+        let text = "<a>text</b></a>";
+        assert!(XmlDom::parse_str(text).is_err());
+        let result = XmlDom::parse_reader_recovering(text.as_bytes(), &options).unwrap();
+        assert_eq!(XmlItem::as_string(result.root.unwrap()), "<a>text</a>".to_string());
+        assert_eq!(result.diagnostics.len(), 1);
+
+        let options = options.set_max_nodes(2);
+        assert!(XmlDom::parse_str_recovering("<a><b/><c/></a>", &options).is_err());
+
+        assert!(XmlDom::open_recovering(Path::new("./tests/test.xml"), &options).is_err());
+        let result = XmlDom::open_recovering(Path::new("./tests/test.xml"),
+                                             &ParserOptions::default())
+                         .unwrap();
+        assert!(result.root.is_some());
+        assert!(result.diagnostics.is_empty());
+    }
+
+    #[test]
+    fn parse_preserving() {
+        let options = ParserOptions::strict().set_preserve_formatting(true);
+        let text = "<?xml version='1.0'?>\n<!-- c -->\n<a  y='&lt;' x=\"2\" >\n  <b/>\n  \
+                    &#65; <![CDATA[<>]]><?pi  data?>\n</a >\n";
+        let root = XmlDom::parse_str_with(text, &options).unwrap();
+        assert_eq!(XmlItem::as_string(root.clone()), text.to_string());
+        assert_eq!(XmlItem::get_markup(root.clone()),
+                   Some("<a  y='&lt;' x=\"2\" >".to_string()));
+        assert_eq!(XmlItem::get_end_markup(root.clone()), Some("</a >".to_string()));
+        assert_eq!(XmlItem::get_prolog(root.clone()).len(), 3);
+
+        let nodes = XmlItem::get_nodes(root.clone());
+        XmlItem::add_node(nodes[1].clone(), XmlItem::new("c".to_string()));
+        XmlItem::set_text(nodes[2].clone(), "\n  B ".to_string());
+        XmlItem::set_declaration(root.clone(),
+                                 Some(XmlDeclaration::new("1.0".to_string())));
+        assert_eq!(XmlItem::as_string(root.clone()),
+                   "<?xml version=\"1.0\"?>\n<!-- c -->\n<a  y='&lt;' x=\"2\" >\n  \
+                    <b>\n    <c/>\n  </b>\n  B <![CDATA[<>]]><?pi  data?>\n</a >\n"
+                       .to_string());
+
+        // New nodes are indented like the ones around them.
+        let root = XmlDom::parse_str_with("<a>\n  <b/>\n</a>", &options).unwrap();
+        let c = XmlItem::add_node(root.clone(), XmlItem::new("c".to_string()));
+        XmlItem::add_node(c.clone(), XmlItem::new("d".to_string()));
+        XmlItem::add_node(root.clone(), XmlItem::new("e".to_string()));
+        assert_eq!(XmlItem::as_string(root.clone()),
+                   "<a>\n  <b/>\n  <c>\n    <d/>\n  </c>\n  <e/>\n</a>".to_string());
+
+        // Elements closed by recovery get end tags.
+        let result = XmlDom::parse_str_recovering("<a>\n<b>\n</a>", &options).unwrap();
+        assert_eq!(XmlItem::as_string(result.root.unwrap()),
+                   "<a>\n<b>\n</b></a>".to_string());
+
+        let root = XmlDom::parse_str(text).unwrap();
+        assert_eq!(XmlItem::get_markup(root.clone()), None);
+        assert!(XmlItem::as_string(root.clone()).starts_with("<?xml version=\"1.0\"?>\n<!-- c -->"));
+    }
+
+    #[test]
+    fn attribute_order() {
+        let text = "<a z=\"1\" b=\"2\" m=\"3\"><c y=\"4\" x=\"5\"/></a>";
+        let root = XmlDom::parse_str(text).unwrap();
+        assert_eq!(XmlItem::as_string(root.clone()),
+                   "<a z=\"1\" b=\"2\" m=\"3\">\n\t<c y=\"4\" x=\"5\"/>\n</a>".to_string());
+
+        let filename = ::std::env::temp_dir().join("libsgl_xml_attribute_order.xml");
+        XmlDom::save_file_with(root.clone(),
+                               &filename,
+                               &WriterOptions::default().set_sort_attributes(true))
+            .unwrap();
+        let mut saved = String::new();
+        File::open(&filename).unwrap().read_to_string(&mut saved).unwrap();
+        assert_eq!(saved,
+                   "<a b=\"2\" m=\"3\" z=\"1\">\n\t<c x=\"5\" y=\"4\"/>\n</a>".to_string());
+        ::std::fs::remove_file(&filename).unwrap();
+
+        // Tags, that are kept as they were read, are not sorted.
+        let options = ParserOptions::default().set_preserve_formatting(true);
+        let root = XmlDom::parse_str_with(text, &options).unwrap();
+        assert_eq!(XmlItem::as_string_with(root.clone(),
+                                           &WriterOptions::default().set_sort_attributes(true)),
+                   text.to_string());
+    }
 }
@@ -0,0 +1,497 @@
+// Licensed to the Square Gears Logic (SGL) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The SGL licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+// http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use xml_error::{XmlError, XmlPosition};
+
+/// Windows-1252 characters for bytes 0x80..0x9F. The five bytes, that code page leaves
+/// undefined, map to C1 control characters with the same code, like browsers do.
+const WINDOWS_1252: [char; 32] = ['\u{20AC}', '\u{81}', '\u{201A}', '\u{192}', '\u{201E}',
+                                  '\u{2026}', '\u{2020}', '\u{2021}', '\u{2C6}', '\u{2030}',
+                                  '\u{160}', '\u{2039}', '\u{152}', '\u{8D}', '\u{17D}',
+                                  '\u{8F}', '\u{90}', '\u{2018}', '\u{2019}', '\u{201C}',
+                                  '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}', '\u{2DC}',
+                                  '\u{2122}', '\u{161}', '\u{203A}', '\u{153}', '\u{9D}',
+                                  '\u{17E}', '\u{178}'];
+
+/// Character encodings, that documents may be read and written in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum XmlEncoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    Iso8859_1,
+    Windows1252,
+    /// US-ASCII, that holds only characters up to U+007F.
+    Ascii,
+}
+
+impl XmlEncoding {
+    /// Finds encoding by a name, such as one in `<?xml encoding="..."?>`.
+    /// Names are case insensitive. Plain "UTF-16" means little endian,
+    /// though byte order mark, if any, takes precedence on load.
+    pub fn from_label(label: &str) -> Option<XmlEncoding> {
+        match label.trim().to_lowercase().as_str() {
+            "utf-8" | "utf8" => Some(XmlEncoding::Utf8),
+            "utf-16" | "utf-16le" => Some(XmlEncoding::Utf16Le),
+            "utf-16be" => Some(XmlEncoding::Utf16Be),
+            "iso-8859-1" | "iso8859-1" | "iso_8859-1" | "latin1" | "latin-1" | "l1" => {
+                Some(XmlEncoding::Iso8859_1)
+            }
+            "windows-1252" | "cp1252" => Some(XmlEncoding::Windows1252),
+            "us-ascii" | "ascii" => Some(XmlEncoding::Ascii),
+            _ => None,
+        }
+    }
+
+    /// Canonical name to be written in XML declaration.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            XmlEncoding::Utf8 => "UTF-8",
+            // Written documents start with byte order mark, that tells the byte order.
+            XmlEncoding::Utf16Le | XmlEncoding::Utf16Be => "UTF-16",
+            XmlEncoding::Iso8859_1 => "ISO-8859-1",
+            XmlEncoding::Windows1252 => "windows-1252",
+            XmlEncoding::Ascii => "US-ASCII",
+        }
+    }
+
+    /// Guesses encoding of a document: byte order mark goes first,
+    /// then the way `<?xml` is encoded, then encoding from XML declaration.
+    /// Documents without any of these are UTF-8.
+    pub fn detect(bytes: &[u8]) -> Result<XmlEncoding, XmlError> {
+        if bytes.starts_with(&[0xEF, 0xBB, 0xBF]) {
+            return Ok(XmlEncoding::Utf8);
+        }
+        if bytes.starts_with(&[0xFF, 0xFE]) || bytes.starts_with(&[b'<', 0, b'?', 0]) {
+            return Ok(XmlEncoding::Utf16Le);
+        }
+        if bytes.starts_with(&[0xFE, 0xFF]) || bytes.starts_with(&[0, b'<', 0, b'?']) {
+            return Ok(XmlEncoding::Utf16Be);
+        }
+
+        let label = match Self::declared_label(bytes) {
+            Some(label) => label,
+            None => return Ok(XmlEncoding::Utf8),
+        };
+        match Self::from_label(&label) {
+            Some(XmlEncoding::Utf16Le) | Some(XmlEncoding::Utf16Be) => {
+                Err(XmlError::Encoding {
+                    message: format!("document declares encoding \"{}\", but it is not \
+                                      UTF-16 encoded",
+                                     label),
+                    position: XmlPosition::default(),
+                })
+            }
+            Some(encoding) => Ok(encoding),
+            None => {
+                Err(XmlError::Encoding {
+                    message: format!("unsupported encoding \"{}\"", label),
+                    position: XmlPosition::default(),
+                })
+            }
+        }
+    }
+
+    /// Value of `encoding` pseudo-attribute of the XML declaration,
+    /// read from ASCII compatible bytes.
+    fn declared_label(bytes: &[u8]) -> Option<String> {
+        if !bytes.starts_with(b"<?xml") {
+            return None;
+        }
+        let end = bytes.windows(2).position(|pair| pair == b"?>")?;
+        let declaration = String::from_utf8_lossy(&bytes[..end]).into_owned();
+
+        let rest = &declaration[declaration.find("encoding")? + "encoding".len()..];
+        let rest = rest.trim_start().strip_prefix('=')?.trim_start();
+        let quote = rest.chars().next().filter(|&ch| ch == '"' || ch == '\'')?;
+        let rest = &rest[1..];
+        Some(rest[..rest.find(quote)?].to_string())
+    }
+
+    /// Turns bytes of a document into text, dropping byte order mark.
+    pub fn decode(&self, bytes: &[u8]) -> Result<String, XmlError> {
+        self.decode_part(&bytes[self.bom_len(bytes)..], XmlPosition::default())
+    }
+
+    /// Length of byte order mark at the beginning of bytes, if there is one.
+    fn bom_len(&self, bytes: &[u8]) -> usize {
+        let bom: &[u8] = match *self {
+            XmlEncoding::Utf8 => &[0xEF, 0xBB, 0xBF],
+            XmlEncoding::Utf16Le => &[0xFF, 0xFE],
+            XmlEncoding::Utf16Be => &[0xFE, 0xFF],
+            XmlEncoding::Iso8859_1 | XmlEncoding::Windows1252 | XmlEncoding::Ascii => return 0,
+        };
+        if bytes.starts_with(bom) {
+            bom.len()
+        } else {
+            0
+        }
+    }
+
+    /// Length of bytes, that make whole characters. The rest may be completed
+    /// by the next part of the document.
+    fn complete_len(&self, bytes: &[u8]) -> usize {
+        match *self {
+            XmlEncoding::Utf8 => {
+                match ::std::str::from_utf8(bytes) {
+                    Err(ref err) if err.error_len().is_none() => err.valid_up_to(),
+                    _ => bytes.len(),
+                }
+            }
+            XmlEncoding::Utf16Le | XmlEncoding::Utf16Be => {
+                let len = bytes.len() - bytes.len() % 2;
+                let last = match (*self, &bytes[..len]) {
+                    (_, &[]) => return 0,
+                    (XmlEncoding::Utf16Be, &[.., high, low]) => u16::from_be_bytes([high, low]),
+                    (_, &[.., low, high]) => u16::from_le_bytes([low, high]),
+                    _ => return len,
+                };
+                // High surrogate waits for the low one.
+                if (0xD800..0xDC00).contains(&last) {
+                    len - 2
+                } else {
+                    len
+                }
+            }
+            XmlEncoding::Iso8859_1 | XmlEncoding::Windows1252 | XmlEncoding::Ascii => bytes.len(),
+        }
+    }
+
+    /// Decodes a part of a document, that starts at the position.
+    fn decode_part(&self, bytes: &[u8], start: XmlPosition) -> Result<String, XmlError> {
+        match *self {
+            XmlEncoding::Utf8 => {
+                match ::std::str::from_utf8(bytes) {
+                    Ok(text) => Ok(text.to_string()),
+                    Err(err) => {
+                        let valid = String::from_utf8_lossy(&bytes[..err.valid_up_to()]);
+                        Err(Self::error("invalid UTF-8 sequence", start.advance(&valid)))
+                    }
+                }
+            }
+            XmlEncoding::Utf16Le | XmlEncoding::Utf16Be => self.decode_utf16(bytes, start),
+            XmlEncoding::Iso8859_1 => Ok(bytes.iter().map(|&byte| byte as char).collect()),
+            XmlEncoding::Windows1252 => {
+                Ok(bytes.iter()
+                        .map(|&byte| match byte {
+                            0x80..=0x9F => WINDOWS_1252[(byte - 0x80) as usize],
+                            _ => byte as char,
+                        })
+                        .collect())
+            }
+            XmlEncoding::Ascii => {
+                match bytes.iter().position(|&byte| byte > 0x7F) {
+                    Some(pos) => {
+                        let valid = String::from_utf8_lossy(&bytes[..pos]);
+                        Err(Self::error("invalid US-ASCII byte", start.advance(&valid)))
+                    }
+                    None => Ok(bytes.iter().map(|&byte| byte as char).collect()),
+                }
+            }
+        }
+    }
+
+    fn decode_utf16(&self, bytes: &[u8], start: XmlPosition) -> Result<String, XmlError> {
+        let units = bytes.chunks(2).map(|pair| {
+            match (*self, pair) {
+                (XmlEncoding::Utf16Be, &[high, low]) => Some(u16::from_be_bytes([high, low])),
+                (_, &[low, high]) => Some(u16::from_le_bytes([low, high])),
+                _ => None,
+            }
+        });
+
+        let mut text = String::with_capacity(bytes.len() / 2);
+        let mut odd_byte = false;
+        let units = units.map_while(|unit| {
+                             odd_byte = unit.is_none();
+                             unit
+                         })
+                         .collect::<Vec<u16>>();
+        for ch in ::std::char::decode_utf16(units) {
+            match ch {
+                Ok(ch) => text.push(ch),
+                Err(_) => {
+                    return Err(Self::error("unpaired UTF-16 surrogate", start.advance(&text)))
+                }
+            }
+        }
+        if odd_byte {
+            return Err(Self::error("odd number of bytes in UTF-16 document",
+                                   start.advance(&text)));
+        }
+        Ok(text)
+    }
+
+    /// Tells if the encoding can represent the character.
+    pub fn can_encode(&self, ch: char) -> bool {
+        match *self {
+            XmlEncoding::Utf8 | XmlEncoding::Utf16Le | XmlEncoding::Utf16Be => true,
+            _ => self.encode_byte(ch).is_some(),
+        }
+    }
+
+    /// Turns text into bytes. UTF-16 text starts with byte order mark.
+    /// Character, that the encoding can't represent, is an error:
+    /// the writer puts character references in place of such characters,
+    /// where XML allows them.
+    pub fn encode(&self, text: &str) -> Result<Vec<u8>, XmlError> {
+        let mut result = Vec::with_capacity(text.len());
+        match *self {
+            XmlEncoding::Utf8 => result.extend_from_slice(text.as_bytes()),
+            XmlEncoding::Utf16Le => {
+                result.extend_from_slice(&[0xFF, 0xFE]);
+                for unit in text.encode_utf16() {
+                    result.extend_from_slice(&unit.to_le_bytes());
+                }
+            }
+            XmlEncoding::Utf16Be => {
+                result.extend_from_slice(&[0xFE, 0xFF]);
+                for unit in text.encode_utf16() {
+                    result.extend_from_slice(&unit.to_be_bytes());
+                }
+            }
+            XmlEncoding::Iso8859_1 | XmlEncoding::Windows1252 | XmlEncoding::Ascii => {
+                for (pos, ch) in text.char_indices() {
+                    match self.encode_byte(ch) {
+                        Some(byte) => result.push(byte),
+                        None => {
+                            let message = format!("{} can't represent '{}'", self.name(), ch);
+                            let position = XmlPosition::end_of(&text[..pos]);
+                            return Err(Self::error(&message, position));
+                        }
+                    }
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    /// Byte of a single byte encoding, that stands for the character.
+    fn encode_byte(&self, ch: char) -> Option<u8> {
+        let code = ch as u32;
+        if *self == XmlEncoding::Ascii {
+            return if code < 0x80 { Some(code as u8) } else { None };
+        }
+        if *self == XmlEncoding::Windows1252 {
+            if let Some(pos) = WINDOWS_1252.iter().position(|&known| known == ch) {
+                return Some(0x80 + pos as u8);
+            }
+            if (0x80..0xA0).contains(&code) {
+                return None;
+            }
+        }
+        if code < 0x100 {
+            return Some(code as u8);
+        }
+        None
+    }
+
+    fn error(message: &str, position: XmlPosition) -> XmlError {
+        XmlError::Encoding {
+            message: message.to_string(),
+            position,
+        }
+    }
+}
+
+/// Longest XML declaration, that is waited for to detect encoding.
+const MAX_DECLARATION_LEN: usize = 1024;
+
+/// Decodes a document, that comes in parts, such as from a socket.
+/// Encoding is detected as soon as there are enough bytes for it,
+/// and characters split between parts are decoded when the next part comes.
+pub struct XmlDecoder {
+    encoding: Option<XmlEncoding>,
+    pending: Vec<u8>,
+    position: XmlPosition,
+}
+
+impl XmlDecoder {
+    pub fn new() -> XmlDecoder {
+        XmlDecoder {
+            encoding: None,
+            pending: vec![],
+            position: XmlPosition::default(),
+        }
+    }
+
+    /// Encoding of the document, if it is detected already.
+    pub fn encoding(&self) -> Option<XmlEncoding> {
+        self.encoding
+    }
+
+    /// Decodes the next part of a document. Bytes, that can't be decoded yet,
+    /// are kept for the next call. Set `is_last` for the last part.
+    pub fn decode(&mut self, bytes: &[u8], is_last: bool) -> Result<String, XmlError> {
+        self.pending.extend_from_slice(bytes);
+
+        let encoding = match self.encoding {
+            Some(encoding) => encoding,
+            None => {
+                if !is_last && !Self::can_detect(&self.pending) {
+                    return Ok(String::new());
+                }
+                let encoding = XmlEncoding::detect(&self.pending)?;
+                let bom_len = encoding.bom_len(&self.pending);
+                self.pending.drain(..bom_len);
+                self.encoding = Some(encoding);
+                encoding
+            }
+        };
+
+        let len = if is_last {
+            self.pending.len()
+        } else {
+            encoding.complete_len(&self.pending)
+        };
+        let text = encoding.decode_part(&self.pending[..len], self.position)?;
+        self.pending.drain(..len);
+        self.position = self.position.advance(&text);
+        Ok(text)
+    }
+
+    /// Tells if there are enough bytes for [XmlEncoding::detect](enum.XmlEncoding.html#method.detect),
+    /// i.e. byte order mark and the whole XML declaration, if any.
+    fn can_detect(bytes: &[u8]) -> bool {
+        let head = &bytes[..bytes.len().min(5)];
+        if head.len() < 4 {
+            return false;
+        }
+        if !b"<?xml".starts_with(head) {
+            return true;
+        }
+        bytes.windows(2).any(|pair| pair == b"?>") || bytes.len() > MAX_DECLARATION_LEN
+    }
+}
+
+impl Default for XmlDecoder {
+    fn default() -> XmlDecoder {
+        XmlDecoder::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{XmlDecoder, XmlEncoding};
+    use xml_error::{XmlError, XmlPosition};
+
+    #[test]
+    fn detection() {
+        assert_eq!(XmlEncoding::detect(b"<a/>").unwrap(), XmlEncoding::Utf8);
+        assert_eq!(XmlEncoding::detect(b"\xEF\xBB\xBF<a/>").unwrap(), XmlEncoding::Utf8);
+        assert_eq!(XmlEncoding::detect(b"\xFF\xFE<\x00").unwrap(), XmlEncoding::Utf16Le);
+        assert_eq!(XmlEncoding::detect(b"\xFE\xFF\x00<").unwrap(), XmlEncoding::Utf16Be);
+        assert_eq!(XmlEncoding::detect(b"\x00<\x00?\x00x").unwrap(), XmlEncoding::Utf16Be);
+        assert_eq!(XmlEncoding::detect(b"<?xml version='1.0' encoding = 'Latin1'?><a/>")
+                       .unwrap(),
+                   XmlEncoding::Iso8859_1);
+        assert_eq!(XmlEncoding::detect(b"<?xml version=\"1.0\" encoding=\"CP1252\"?>").unwrap(),
+                   XmlEncoding::Windows1252);
+        assert_eq!(XmlEncoding::detect(b"<?xml version=\"1.0\"?><a encoding='cp1252'/>")
+                       .unwrap(),
+                   XmlEncoding::Utf8);
+
+        match XmlEncoding::detect(b"<?xml version='1.0' encoding='KOI8-R'?>") {
+            Err(XmlError::Encoding { message, .. }) => {
+                assert_eq!(message, "unsupported encoding \"KOI8-R\"".to_string())
+            }
+            _ => panic!(),
+        }
+        assert!(XmlEncoding::detect(b"<?xml version='1.0' encoding='UTF-16'?>").is_err());
+    }
+
+    #[test]
+    fn decoding() {
+        assert_eq!(XmlEncoding::Utf8.decode(b"\xEF\xBB\xBF<a>\xE2\x98\xBA</a>").unwrap(),
+                   "<a>\u{263A}</a>".to_string());
+        assert_eq!(XmlEncoding::Utf16Le.decode(b"\xFF\xFE<\x00a\x00/\x00>\x00").unwrap(),
+                   "<a/>".to_string());
+        assert_eq!(XmlEncoding::Utf16Be.decode(b"\x00<\xD8\x3D\xDE\x00\x00>").unwrap(),
+                   "<\u{1F600}>".to_string());
+        assert_eq!(XmlEncoding::Iso8859_1.decode(b"caf\xE9 \x80").unwrap(),
+                   "caf\u{E9} \u{80}".to_string());
+        assert_eq!(XmlEncoding::Windows1252.decode(b"caf\xE9 \x80 \x81").unwrap(),
+                   "caf\u{E9} \u{20AC} \u{81}".to_string());
+
+        match XmlEncoding::Utf8.decode(b"<a>\n\xFF</a>") {
+            Err(XmlError::Encoding { position, .. }) => {
+                assert_eq!(position, XmlPosition::new(2, 1, 4))
+            }
+            _ => panic!(),
+        }
+        assert!(XmlEncoding::Utf16Le.decode(b"<\x00\x00\xDC").is_err());
+        assert!(XmlEncoding::Utf16Le.decode(b"<\x00a").is_err());
+    }
+
+    #[test]
+    fn encoding() {
+        let text = "caf\u{E9} \u{20AC} \u{263A}";
+        assert_eq!(XmlEncoding::Iso8859_1.encode("caf\u{E9}").unwrap(), b"caf\xE9".to_vec());
+        assert_eq!(XmlEncoding::Windows1252.encode("caf\u{E9} \u{20AC}").unwrap(),
+                   b"caf\xE9 \x80".to_vec());
+        assert_eq!(XmlEncoding::Windows1252.encode("\u{81}").unwrap(), b"\x81".to_vec());
+        assert_eq!(XmlEncoding::Utf16Be.encode("<\u{1F600}").unwrap(),
+                   b"\xFE\xFF\x00<\xD8\x3D\xDE\x00".to_vec());
+        match XmlEncoding::Iso8859_1.encode(text) {
+            Err(XmlError::Encoding { position, .. }) => {
+                assert_eq!(position, XmlPosition::new(1, 6, 6))
+            }
+            _ => panic!(),
+        }
+        assert!(XmlEncoding::Windows1252.encode("\u{80}").is_err());
+        assert!(XmlEncoding::Ascii.encode("caf\u{E9}").is_err());
+        assert!(XmlEncoding::Ascii.can_encode('~'));
+        assert!(!XmlEncoding::Ascii.can_encode('\u{80}'));
+        assert!(XmlEncoding::Utf8.can_encode('\u{263A}'));
+        assert_eq!(XmlEncoding::from_label("US-ASCII"), Some(XmlEncoding::Ascii));
+        assert_eq!(XmlEncoding::Ascii.decode(b"<a/>").unwrap(), "<a/>".to_string());
+        assert!(XmlEncoding::Ascii.decode(b"<a>\xE9</a>").is_err());
+
+        for &encoding in &[XmlEncoding::Utf8,
+                           XmlEncoding::Utf16Le,
+                           XmlEncoding::Utf16Be,
+                           XmlEncoding::Windows1252] {
+            let bytes = encoding.encode("<a>\u{E9}\u{20AC}</a>").unwrap();
+            assert_eq!(encoding.decode(&bytes).unwrap(), "<a>\u{E9}\u{20AC}</a>".to_string());
+        }
+    }
+
+    #[test]
+    fn decoding_parts() {
+        let text = "<?xml version='1.0'?>\n<a>\u{E9}\u{1F600}</a>";
+        for &encoding in &[XmlEncoding::Utf8, XmlEncoding::Utf16Le, XmlEncoding::Utf16Be] {
+            let bytes = encoding.encode(text).unwrap();
+            for size in 1..bytes.len() {
+                let mut decoder = XmlDecoder::new();
+                let mut result = String::new();
+                for chunk in bytes.chunks(size) {
+                    result.push_str(&decoder.decode(chunk, false).unwrap());
+                }
+                result.push_str(&decoder.decode(&[], true).unwrap());
+                assert_eq!(result, text.to_string());
+                assert_eq!(decoder.encoding(), Some(encoding));
+            }
+        }
+
+        let mut decoder = XmlDecoder::new();
+        assert_eq!(decoder.decode(b"<a>\n\xC3", false).unwrap(), "<a>\n".to_string());
+        match decoder.decode(b"\xA9\xFF", false) {
+            Err(XmlError::Encoding { position, .. }) => {
+                assert_eq!(position, XmlPosition::new(2, 2, 6))
+            }
+            _ => panic!(),
+        }
+    }
+}
@@ -0,0 +1,236 @@
+// Licensed to the Square Gears Logic (SGL) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The SGL licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+// http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// Location of a character in the parsed document.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct XmlPosition {
+    /// Line number, starting from 1.
+    pub line: usize,
+    /// Character number in the line, starting from 1.
+    pub column: usize,
+    /// Byte offset from the beginning of the document, starting from 0.
+    /// Documents in other encodings are counted as UTF-8 text they are decoded to.
+    pub offset: usize,
+}
+
+impl XmlPosition {
+    pub fn new(line: usize, column: usize, offset: usize) -> XmlPosition {
+        XmlPosition {
+            line,
+            column,
+            offset,
+        }
+    }
+
+    /// Position right after the last character of a text.
+    pub fn end_of(text: &str) -> XmlPosition {
+        XmlPosition::default().advance(text)
+    }
+
+    /// Position right after the last character of a text, that starts here.
+    pub fn advance(&self, text: &str) -> XmlPosition {
+        match text.rfind('\n') {
+            Some(pos) => {
+                XmlPosition::new(self.line + text.matches('\n').count(),
+                                 text[pos + 1..].chars().count() + 1,
+                                 self.offset + text.len())
+            }
+            None => {
+                XmlPosition::new(self.line,
+                                 self.column + text.chars().count(),
+                                 self.offset + text.len())
+            }
+        }
+    }
+}
+
+impl Default for XmlPosition {
+    /// The very beginning of a document.
+    fn default() -> XmlPosition {
+        XmlPosition::new(1, 1, 0)
+    }
+}
+
+impl fmt::Display for XmlPosition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
+/// Part of the parsed document, from the first character of a markup
+/// to the position right after the last one.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct XmlSpan {
+    pub start: XmlPosition,
+    pub end: XmlPosition,
+}
+
+impl XmlSpan {
+    pub fn new(start: XmlPosition, end: XmlPosition) -> XmlSpan {
+        XmlSpan { start, end }
+    }
+}
+
+/// Reason why a document can't be read.
+#[derive(Debug)]
+pub enum XmlError {
+    /// Document can't be read from it's source.
+    Io {
+        error: io::Error,
+        position: XmlPosition,
+    },
+    /// Bytes of the document don't match it's encoding.
+    Encoding {
+        message: String,
+        position: XmlPosition,
+    },
+    /// Markup, that can't be parsed, such as attribute without a value.
+    MalformedTag {
+        message: String,
+        position: XmlPosition,
+    },
+    /// Closing tag doesn't match the element it closes.
+    MismatchedCloseTag {
+        expected: String,
+        found: String,
+        position: XmlPosition,
+    },
+    /// Closing tag without any open element, such as one after the root element.
+    UnexpectedCloseTag {
+        found: String,
+        position: XmlPosition,
+    },
+    /// Document ends in the middle of markup, has unclosed elements or no root element.
+    UnexpectedEof {
+        message: String,
+        position: XmlPosition,
+    },
+    /// Document is bigger than [ParserOptions](struct.ParserOptions.html) allow,
+    /// e.g. elements are nested deeper than `max_depth`.
+    LimitExceeded {
+        /// Name of the limit in ParserOptions.
+        limit: &'static str,
+        /// The limit, that is exceeded.
+        value: usize,
+        position: XmlPosition,
+    },
+    /// Markup is fine, but it breaks XML rules, such as a second root element.
+    Syntax {
+        message: String,
+        position: XmlPosition,
+    },
+}
+
+impl XmlError {
+    pub fn position(&self) -> XmlPosition {
+        match *self {
+            XmlError::Io { position, .. } |
+            XmlError::Encoding { position, .. } |
+            XmlError::MalformedTag { position, .. } |
+            XmlError::MismatchedCloseTag { position, .. } |
+            XmlError::UnexpectedCloseTag { position, .. } |
+            XmlError::UnexpectedEof { position, .. } |
+            XmlError::LimitExceeded { position, .. } |
+            XmlError::Syntax { position, .. } => position,
+        }
+    }
+}
+
+impl fmt::Display for XmlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            XmlError::Io { ref error, position } => {
+                write!(f, "I/O error at {}: {}", position, error)
+            }
+            XmlError::Encoding { ref message, position } => {
+                write!(f, "encoding error at {}: {}", position, message)
+            }
+            XmlError::MalformedTag { ref message, position } => {
+                write!(f, "malformed tag at {}: {}", position, message)
+            }
+            XmlError::MismatchedCloseTag { ref expected, ref found, position } => {
+                write!(f,
+                       "mismatched close tag at {}: expected </{}>, found </{}>",
+                       position,
+                       expected,
+                       found)
+            }
+            XmlError::UnexpectedCloseTag { ref found, position } => {
+                write!(f, "unexpected close tag at {}: </{}>", position, found)
+            }
+            XmlError::UnexpectedEof { ref message, position } => {
+                write!(f, "unexpected end of file at {}: {}", position, message)
+            }
+            XmlError::LimitExceeded { limit, value, position } => {
+                write!(f, "limit exceeded at {}: {} is {}", position, limit, value)
+            }
+            XmlError::Syntax { ref message, position } => {
+                write!(f, "syntax error at {}: {}", position, message)
+            }
+        }
+    }
+}
+
+impl Error for XmlError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            XmlError::Io { ref error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for XmlError {
+    /// Error of opening a document, before anything of it is read.
+    fn from(error: io::Error) -> XmlError {
+        XmlError::Io {
+            error,
+            position: XmlPosition::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::error::Error;
+    use std::io;
+
+    use super::{XmlError, XmlPosition};
+
+    #[test]
+    fn display() {
+        let error = XmlError::MismatchedCloseTag {
+            expected: "a".to_string(),
+            found: "b".to_string(),
+            position: XmlPosition::new(3, 7, 42),
+        };
+        assert_eq!(error.to_string(),
+                   "mismatched close tag at line 3, column 7: expected </a>, found </b>"
+                       .to_string());
+        assert_eq!(error.position().offset, 42);
+        assert!(error.source().is_none());
+
+        let error = XmlError::from(io::Error::new(io::ErrorKind::NotFound, "no file"));
+        assert_eq!(error.position(), XmlPosition::default());
+        assert!(error.source().is_some());
+
+        assert_eq!(XmlPosition::end_of("ab\n\u{444}c"), XmlPosition::new(2, 3, 6));
+        assert_eq!(XmlPosition::new(2, 3, 6).advance("de"), XmlPosition::new(2, 5, 8));
+    }
+}
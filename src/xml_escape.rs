@@ -0,0 +1,249 @@
+// Licensed to the Square Gears Logic (SGL) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The SGL licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+// http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Entity and character references.
+
+/// Replaces predefined entities `&amp;` `&lt;` `&gt;` `&quot;` `&apos;`
+/// and character references like `&#169;` or `&#x263A;` with characters they stand for.
+///
+/// Unknown entities and lonely ampersands are left as is.
+/// Use [decode_attribute](fn.decode_attribute.html) to keep track of unknown entities.
+/// Reference to a character, that is not allowed in XML, is an error.
+pub fn decode(text: &str) -> Result<String, String> {
+    if !text.contains('&') {
+        return Ok(text.to_string());
+    }
+
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(pos) = rest.find('&') {
+        result.push_str(&rest[..pos]);
+        rest = &rest[pos..];
+
+        let end = match rest.find(';') {
+            Some(end) => end,
+            None => break,
+        };
+        let name = &rest[1..end];
+        match decode_reference(name)? {
+            Some(ch) => {
+                result.push(ch);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                result.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    result.push_str(rest);
+
+    Ok(result)
+}
+
+/// Decodes attribute value the same way as decode(). If the value refers to entities,
+/// that are not predefined, it's also returned escaped for writing,
+/// with these references kept as they are.
+pub fn decode_attribute(text: &str) -> Result<(String, Option<String>), String> {
+    let mut escaped = String::new();
+    let mut start = 0;
+    let mut search = 0;
+    while let Some(found) = text[search..].find('&') {
+        let pos = search + found;
+        search = pos + 1;
+        if let Some(name) = unresolved_entity(&text[pos + 1..]) {
+            let end = pos + name.len() + 2;
+            escaped.push_str(&escape_attribute(&decode(&text[start..pos])?));
+            escaped.push_str(&text[pos..end]);
+            start = end;
+            search = end;
+        }
+    }
+
+    let value = decode(text)?;
+    if start == 0 {
+        return Ok((value, None));
+    }
+    escaped.push_str(&escape_attribute(&decode(&text[start..])?));
+    Ok((value, Some(escaped)))
+}
+
+/// Name of the entity, that text right after `&` refers to, unless it's a predefined entity
+/// or a character reference. Such entities may only be declared by DTD, which is not read,
+/// so references to them are kept as they are.
+pub fn unresolved_entity(text: &str) -> Option<&str> {
+    let end = text.find(|ch: char| !is_name_char(ch))?;
+    let name = &text[..end];
+    if !text[end..].starts_with(';') || !is_name(name) {
+        return None;
+    }
+    match name {
+        "amp" | "lt" | "gt" | "quot" | "apos" => None,
+        _ => Some(name),
+    }
+}
+
+/// Names of entities, that are not predefined, referred to by escaped text.
+pub fn unresolved_entities(text: &str) -> Vec<&str> {
+    text.match_indices('&')
+        .filter_map(|(pos, _)| unresolved_entity(&text[pos + 1..]))
+        .collect()
+}
+
+/// Position of the first `&` in markup, that doesn't start an entity
+/// or character reference.
+pub fn bare_ampersand(text: &str) -> Option<usize> {
+    text.match_indices('&')
+        .map(|(pos, _)| pos)
+        .find(|pos| {
+            let rest = &text[pos + 1..];
+            let end = rest.find(|ch: char| ch != '#' && !is_name_char(ch)).unwrap_or(rest.len());
+            let name = &rest[..end];
+            !rest[end..].starts_with(';') || !is_name(name) && !is_char_reference(name)
+        })
+}
+
+/// `#N` or `#xN` of a character reference `&#N;`.
+fn is_char_reference(name: &str) -> bool {
+    match name.strip_prefix("#x") {
+        Some(hex) => !hex.is_empty() && hex.chars().all(|ch| ch.is_ascii_hexdigit()),
+        None => {
+            match name.strip_prefix('#') {
+                Some(dec) => !dec.is_empty() && dec.chars().all(|ch| ch.is_ascii_digit()),
+                None => false,
+            }
+        }
+    }
+}
+
+/// Name of an element, attribute, entity or processing instruction target.
+pub fn is_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(ch) if ch.is_alphabetic() || ch == '_' || ch == ':' => {}
+        _ => return false,
+    }
+    chars.all(is_name_char)
+}
+
+pub fn is_name_char(ch: char) -> bool {
+    ch.is_alphanumeric() || "_:.-\u{B7}".contains(ch)
+}
+
+fn decode_reference(name: &str) -> Result<Option<char>, String> {
+    let code = if let Some(hex) = name.strip_prefix("#x") {
+        u32::from_str_radix(hex, 16).ok()
+    } else if let Some(dec) = name.strip_prefix('#') {
+        dec.parse::<u32>().ok()
+    } else {
+        return Ok(match name {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => None,
+        });
+    };
+
+    match code {
+        Some(code) => {
+            match ::std::char::from_u32(code) {
+                Some(ch) if is_xml_char(ch) => Ok(Some(ch)),
+                _ => Err(format!("invalid character reference \"&{};\"", name)),
+            }
+        }
+        None => Ok(None),
+    }
+}
+
+/// Char ::= #x9 | #xA | #xD | [#x20-#xD7FF] | [#xE000-#xFFFD] | [#x10000-#x10FFFF]
+pub fn is_xml_char(ch: char) -> bool {
+    matches!(ch,
+             '\u{9}' | '\u{A}' | '\u{D}' | '\u{20}'..='\u{D7FF}' | '\u{E000}'..='\u{FFFD}' |
+             '\u{10000}'..='\u{10FFFF}')
+}
+
+/// Escapes text content. `>` is escaped too, so "]]>" never appears in output.
+pub fn escape_text(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    for ch in text.chars() {
+        match ch {
+            '&' => result.push_str("&amp;"),
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '\r' => result.push_str("&#13;"),
+            _ => result.push(ch),
+        }
+    }
+    result
+}
+
+/// Escapes value of an attribute, that is written in double quotes.
+/// Line breaks and tabs are escaped, otherwise other XML parsers would turn them into spaces.
+/// This parser doesn't normalize attribute values and reads them back as they are either way.
+pub fn escape_attribute(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    for ch in value.chars() {
+        match ch {
+            '&' => result.push_str("&amp;"),
+            '<' => result.push_str("&lt;"),
+            '"' => result.push_str("&quot;"),
+            '\t' => result.push_str("&#9;"),
+            '\n' => result.push_str("&#10;"),
+            '\r' => result.push_str("&#13;"),
+            _ => result.push(ch),
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::{bare_ampersand, decode, decode_attribute, escape_attribute, escape_text,
+                unresolved_entities};
+
+    #[test]
+    fn decoding() {
+        assert_eq!(decode("a &amp; b &lt;&gt;&quot;&apos;"),
+                   Ok("a & b <>\"'".to_string()));
+        assert_eq!(decode("&#169; &#x263A; &#X41;"),
+                   Ok("\u{A9} \u{263A} &#X41;".to_string()));
+        assert_eq!(decode("AT&T; a & b; &unknown; &"),
+                   Ok("AT&T; a & b; &unknown; &".to_string()));
+        assert!(decode("&#0;").is_err());
+        assert!(decode("&#xD800;").is_err());
+
+        assert_eq!(decode_attribute("a &amp; b"), Ok(("a & b".to_string(), None)));
+        assert_eq!(decode_attribute("&co; &lt;&amp;co;\"& &x.y;"),
+                   Ok(("&co; <&co;\"& &x.y;".to_string(),
+                       Some("&co; &lt;&amp;co;&quot;&amp; &x.y;".to_string()))));
+        assert_eq!(unresolved_entities("&a; &amp; &#38; & b; &c;"), vec!["a", "c"]);
+        assert_eq!(bare_ampersand("&a; &amp; &#38; &#x26;"), None);
+        assert_eq!(bare_ampersand("&amp; & b;"), Some(6));
+        assert_eq!(bare_ampersand("&#X41; &1;"), Some(0));
+        assert_eq!(bare_ampersand("&amp"), Some(0));
+    }
+
+    #[test]
+    fn escaping() {
+        assert_eq!(escape_text("a < b && c > d \"'"),
+                   "a &lt; b &amp;&amp; c &gt; d \"'".to_string());
+        assert_eq!(escape_attribute("<\"a\" & 'b'>\n"),
+                   "&lt;&quot;a&quot; &amp; 'b'>&#10;".to_string());
+        assert_eq!(decode(&escape_attribute("\t<&>\"\r\n")),
+                   Ok("\t<&>\"\r\n".to_string()));
+    }
+}
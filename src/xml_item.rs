@@ -15,6 +15,14 @@
 pub use std::rc::Rc;
 pub use std::cell::RefCell;
 pub use std::collections::HashMap;
+use std::rc::Weak;
+
+use xml_attributes::XmlAttributes;
+use xml_encoding::XmlEncoding;
+use xml_error::{XmlError, XmlSpan};
+use xml_escape;
+use xml_writer::{self, XmlDocumentWriter, XmlWriterNode};
+use xml_writer_options::WriterOptions;
 
 /// Any Element in DOM structure is a Counted Reference to Cell,
 /// that contains Option of actual XmlItem.
@@ -47,32 +55,243 @@ pub use std::collections::HashMap;
 ///
 pub type XmlItemRc = Rc<RefCell<Option<XmlItem>>>;
 
+/// Kind of a DOM node.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum XmlItemKind {
+    /// `<name attribute="value">...</name>`
+    Element,
+    /// Character data of the parent element. Has no name, only value.
+    Text,
+    /// `<!--value-->`
+    Comment,
+    /// `<![CDATA[value]]>`. Same as Text, but value is never escaped.
+    CData,
+    /// `<?name value?>`, where name is a target of the instruction and value is it's data.
+    ProcessingInstruction,
+    /// `<!DOCTYPE name ...>`. Lives in the prolog, see [XmlDoctype](struct.XmlDoctype.html).
+    Doctype,
+    /// `&name;` in text, where name is an entity, that is not predefined.
+    /// Such entities are declared by DTD, which is not read, so they are kept unexpanded.
+    EntityReference,
+}
+
+/// XML declaration, such as `<?xml version="1.0" encoding="UTF-8" standalone="yes"?>`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct XmlDeclaration {
+    pub version: String,
+    /// Name of the document encoding. Documents are saved in this encoding,
+    /// if [XmlEncoding](enum.XmlEncoding.html) knows it, or in UTF-8 otherwise.
+    pub encoding: Option<String>,
+    pub standalone: Option<bool>,
+}
+
+impl XmlDeclaration {
+    pub fn new(version: String) -> XmlDeclaration {
+        XmlDeclaration {
+            version,
+            encoding: None,
+            standalone: None,
+        }
+    }
+
+    pub fn as_string(&self) -> String {
+        let mut result = format!("<?xml version=\"{}\"", self.version);
+        if let Some(ref encoding) = self.encoding {
+            result = format!("{} encoding=\"{}\"", result, encoding);
+        }
+        if let Some(standalone) = self.standalone {
+            result = format!("{} standalone=\"{}\"",
+                             result,
+                             if standalone { "yes" } else { "no" });
+        }
+        format!("{}?>", result)
+    }
+}
+
+/// Document type declaration, such as
+/// `<!DOCTYPE root PUBLIC "-//SGL//Root//EN" "root.dtd" [ <!ENTITY a "b"> ]>`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct XmlDoctype {
+    /// Name of the root element.
+    pub name: String,
+    /// Public identifier. XML allows it only together with `system_id`.
+    pub public_id: Option<String>,
+    pub system_id: Option<String>,
+    /// Text between `[` and `]`, kept as is.
+    pub internal_subset: Option<String>,
+}
+
+impl XmlDoctype {
+    pub fn new(name: String) -> XmlDoctype {
+        XmlDoctype {
+            name,
+            public_id: None,
+            system_id: None,
+            internal_subset: None,
+        }
+    }
+
+    pub fn as_string(&self) -> String {
+        let mut result = format!("<!DOCTYPE {}", self.name);
+        if let Some(ref public_id) = self.public_id {
+            result = format!("{} PUBLIC {}", result, Self::quote(public_id));
+            if let Some(ref system_id) = self.system_id {
+                result = format!("{} {}", result, Self::quote(system_id));
+            }
+        } else if let Some(ref system_id) = self.system_id {
+            result = format!("{} SYSTEM {}", result, Self::quote(system_id));
+        }
+        if let Some(ref internal_subset) = self.internal_subset {
+            result = format!("{} [{}]", result, internal_subset);
+        }
+        format!("{}>", result)
+    }
+
+    /// Names of general entities, declared by the internal subset.
+    pub fn entities(&self) -> Vec<String> {
+        let subset = match self.internal_subset {
+            Some(ref subset) => subset,
+            None => return vec![],
+        };
+        subset.split("<!ENTITY")
+              .skip(1)
+              .filter_map(|declaration| {
+                  let name = declaration.trim_start()
+                                        .split(|ch: char| ch.is_whitespace())
+                                        .next()
+                                        .unwrap_or("");
+                  // Parameter entities, `<!ENTITY % name ...>`, are for DTD only.
+                  if name.is_empty() || name == "%" {
+                      return None;
+                  }
+                  Some(name.to_string())
+              })
+              .collect()
+    }
+
+    /// Literals can't be escaped, so quote character is chosen by the content.
+    fn quote(literal: &str) -> String {
+        if literal.contains('"') {
+            return format!("'{}'", literal);
+        }
+        format!("\"{}\"", literal)
+    }
+}
+
 /// Represents an Element of XML DOM structure.
 ///
 /// Each Node of DOM structure contains reference to it's parent and an array 
 /// of child nodes. Each child node may contain more nodes and so on.
 ///
+/// Parents own their children, but not the other way around, so the whole tree
+/// is freed once the last reference to it's root is dropped.
+///
 /// Generally memory model is handled by [XmlItemRc](../XmlItemRc) structure.
 /// Because of XmlItemRc internal complexity moste operations on it done
 /// via assosiated function. To access option simply call borrow_mut() on it.
 pub struct XmlItem {
+    /// Node kind. Everything but Element stores its content in `value`.
+    pub kind: XmlItemKind,
     /// Node name
     pub name: String,
-    /// Map of node attributes. Position is not guaranteed.
-    pub attributes: HashMap<String, String>,
-    /// Vector of sub-nodes 
+    /// Content of a Text, Comment or CData node, data of a ProcessingInstruction
+    /// or internal subset of a Doctype. Empty for elements.
+    /// Text is kept unescaped, as_string() escapes it when needed.
+    pub value: String,
+    /// Node attributes with unescaped values, in the order they were read or set.
+    pub attributes: XmlAttributes,
+    /// Public identifier of a Doctype.
+    pub public_id: Option<String>,
+    /// System identifier of a Doctype.
+    pub system_id: Option<String>,
+    /// Vector of sub-nodes, including text, in document order.
     pub nodes: Vec<XmlItemRc>,
-    /// The parent of this node. None assigned for the first item in DOM structure.
-    pub parent: XmlItemRc, // None by default.
+    /// The parent of this node. Dangling for the first item in DOM structure.
+    /// The link is weak, so children don't keep their parent alive.
+    pub parent: Weak<RefCell<Option<XmlItem>>>,
+    /// XML declaration of the document. Only the first item in DOM structure has it.
+    pub declaration: Option<XmlDeclaration>,
+    /// Nodes in front of the root element, such as comments.
+    /// Only the first item in DOM structure has them.
+    pub prolog: Vec<XmlItemRc>,
+    /// Nodes after the root element. Only the first item in DOM structure has them.
+    pub epilog: Vec<XmlItemRc>,
+    /// Where the node is in the parsed document. None for nodes created by hand.
+    pub span: Option<XmlSpan>,
+    /// Where attributes are in the parsed document.
+    pub attribute_spans: HashMap<String, XmlSpan>,
+    /// Original markup of the node, or the start tag of an element, when the document
+    /// is read with `preserve_formatting`. It's printed instead of the node,
+    /// until the node is changed by set_attribute() or set_text().
+    pub markup: Option<String>,
+    /// Original end tag of an element, empty for `<name/>`. Elements, that have it,
+    /// keep layout of their nodes. Nodes added later are indented like the ones read.
+    pub end_markup: Option<String>,
+    /// Original XML declaration. Only the first item in DOM structure has it.
+    pub declaration_markup: Option<String>,
 }
 
 impl XmlItem {
     pub fn new(name: String) -> XmlItemRc {
+        Self::new_item(XmlItemKind::Element, name, "".to_string())
+    }
+
+    /// Creates a Text node. Add it to an element with add_node() or simply use add_text().
+    pub fn new_text(text: String) -> XmlItemRc {
+        Self::new_item(XmlItemKind::Text, "".to_string(), text)
+    }
+
+    /// Creates a Comment node. Text is stored as is, so keep spaces around it if needed.
+    pub fn new_comment(text: String) -> XmlItemRc {
+        Self::new_item(XmlItemKind::Comment, "".to_string(), text)
+    }
+
+    /// Creates a CData node. Text is kept byte-for-byte.
+    pub fn new_cdata(text: String) -> XmlItemRc {
+        Self::new_item(XmlItemKind::CData, "".to_string(), text)
+    }
+
+    /// Creates an EntityReference node, that is printed as `&name;`.
+    pub fn new_entity_reference(name: String) -> XmlItemRc {
+        Self::new_item(XmlItemKind::EntityReference, name, "".to_string())
+    }
+
+    /// Creates a ProcessingInstruction node.
+    pub fn new_processing_instruction(target: String, data: String) -> XmlItemRc {
+        Self::new_item(XmlItemKind::ProcessingInstruction, target, data)
+    }
+
+    /// Creates a Doctype node. Put it into the prolog with add_prolog_node()
+    /// or simply use set_doctype().
+    pub fn new_doctype(doctype: XmlDoctype) -> XmlItemRc {
+        let result = Self::new_item(XmlItemKind::Doctype,
+                                    doctype.name,
+                                    doctype.internal_subset.unwrap_or_default());
+        if let Some(ref mut xml_item) = *result.borrow_mut() {
+            xml_item.public_id = doctype.public_id;
+            xml_item.system_id = doctype.system_id;
+        }
+        result
+    }
+
+    fn new_item(kind: XmlItemKind, name: String, value: String) -> XmlItemRc {
         Rc::new(RefCell::new(Some(XmlItem {
-            parent: Self::get_empty_item(),
+            kind,
+            parent: Weak::new(),
             nodes: vec![],
-            name: name,
-            attributes: HashMap::new(),
+            name,
+            value,
+            attributes: XmlAttributes::new(),
+            public_id: None,
+            system_id: None,
+            declaration: None,
+            prolog: vec![],
+            epilog: vec![],
+            span: None,
+            attribute_spans: HashMap::new(),
+            markup: None,
+            end_markup: None,
+            declaration_markup: None,
         })))
     }
 
@@ -80,7 +299,7 @@ impl XmlItem {
     pub fn add_node(parent: XmlItemRc, node: XmlItemRc) -> XmlItemRc {
 
         if let &mut Some(ref mut xml_item) = &mut *node.borrow_mut() {
-            xml_item.parent = parent.clone();
+            xml_item.parent = Rc::downgrade(&parent);
         }
 
         if let &mut Some(ref mut xml_item) = &mut *parent.borrow_mut() {
@@ -90,48 +309,264 @@ impl XmlItem {
         node
     }
 
+    /// Appends a Text node to a given parent.
+    pub fn add_text(parent: XmlItemRc, text: String) -> XmlItemRc {
+        Self::add_node(parent, Self::new_text(text))
+    }
+
     pub fn set_attribute(rc: XmlItemRc, attribute: String, value: String) {
         if let &mut Some(ref mut test) = &mut *rc.borrow_mut() {
             test.attributes.insert(attribute, value);
+            test.markup = None;
+        }
+    }
+
+    /// Returns original markup of the node, or the start tag of an element,
+    /// if the document is read with formatting preserved.
+    pub fn get_markup(rc: XmlItemRc) -> Option<String> {
+        match *rc.borrow() {
+            Some(ref xml_item) => xml_item.markup.clone(),
+            None => None,
+        }
+    }
+
+    /// Sets markup, that is printed instead of the node. Pass None to print the node
+    /// as it is now, e.g. after changing it's fields directly.
+    pub fn set_markup(rc: XmlItemRc, markup: Option<String>) {
+        if let Some(ref mut xml_item) = *rc.borrow_mut() {
+            xml_item.markup = markup;
+        }
+    }
+
+    pub fn get_end_markup(rc: XmlItemRc) -> Option<String> {
+        match *rc.borrow() {
+            Some(ref xml_item) => xml_item.end_markup.clone(),
+            None => None,
+        }
+    }
+
+    pub fn set_end_markup(rc: XmlItemRc, markup: Option<String>) {
+        if let Some(ref mut xml_item) = *rc.borrow_mut() {
+            xml_item.end_markup = markup;
+        }
+    }
+
+    /// Returns where the node is in the parsed document, from the beginning
+    /// of it's start tag to the end of it's end tag. None for nodes created by hand.
+    pub fn get_span(rc: XmlItemRc) -> Option<XmlSpan> {
+        match *rc.borrow() {
+            Some(ref xml_item) => xml_item.span,
+            None => None,
+        }
+    }
+
+    pub fn set_span(rc: XmlItemRc, span: Option<XmlSpan>) {
+        if let &mut Some(ref mut xml_item) = &mut *rc.borrow_mut() {
+            xml_item.span = span;
+        }
+    }
+
+    /// Returns where `name="value"` of an attribute is in the parsed document.
+    pub fn get_attribute_span(rc: XmlItemRc, attribute: &str) -> Option<XmlSpan> {
+        match *rc.borrow() {
+            Some(ref xml_item) => xml_item.attribute_spans.get(attribute).cloned(),
+            None => None,
+        }
+    }
+
+    pub fn set_attribute_span(rc: XmlItemRc, attribute: String, span: XmlSpan) {
+        if let &mut Some(ref mut xml_item) = &mut *rc.borrow_mut() {
+            xml_item.attribute_spans.insert(attribute, span);
         }
     }
 
     pub fn get_name(rc: XmlItemRc) -> String {
-        match &mut *rc.borrow_mut() {
-            &mut Some(ref mut xml_item) => {
-                return xml_item.name.clone();
-            }
-            _ => {
-                return "".to_string();
-            }
-        };
+        match *rc.borrow() {
+            Some(ref xml_item) => xml_item.name.clone(),
+            None => "".to_string(),
+        }
     }
 
-    pub fn get_nodes(rc: XmlItemRc) -> Vec<XmlItemRc> {
-        match &mut *rc.borrow_mut() {
-            &mut Some(ref mut xml_item) => {
-                // TODO: How vector of Rc behaves?
-                let mut result: Vec<XmlItemRc> = vec![];
+    pub fn get_kind(rc: XmlItemRc) -> XmlItemKind {
+        match *rc.borrow() {
+            Some(ref xml_item) => xml_item.kind,
+            None => XmlItemKind::Element,
+        }
+    }
+
+    /// Returns content of a non-element node, or all Text and CData sub-nodes
+    /// of an element glued together. Entity references are glued in as `&name;`.
+    pub fn get_text(rc: XmlItemRc) -> String {
+        match *rc.borrow() {
+            Some(ref xml_item) => {
+                if xml_item.kind != XmlItemKind::Element {
+                    return xml_item.character_data();
+                }
+                let mut result = "".to_string();
                 for node in &xml_item.nodes {
-                    result.push(node.clone());
+                    if let Some(ref node) = *node.borrow() {
+                        if node.is_character_data() {
+                            result.push_str(&node.character_data());
+                        }
+                    }
                 }
-                return result;
+                result
             }
-            _ => {
-                return vec![];
+            None => "".to_string(),
+        }
+    }
+
+    /// Replaces content of a non-element node. For an element all of it's Text, CData
+    /// and EntityReference sub-nodes are replaced with a single Text node, placed where the first of them was.
+    pub fn set_text(rc: XmlItemRc, text: String) {
+        if Self::get_kind(rc.clone()) != XmlItemKind::Element {
+            if let Some(ref mut xml_item) = *rc.borrow_mut() {
+                xml_item.value = text;
+                xml_item.markup = None;
             }
-        };
+            return;
+        }
+
+        let node = Self::new_text(text);
+        if let Some(ref mut xml_item) = *node.borrow_mut() {
+            xml_item.parent = Rc::downgrade(&rc);
+        }
+        if let Some(ref mut xml_item) = *rc.borrow_mut() {
+            let is_text = |node: &XmlItemRc| match *node.borrow() {
+                Some(ref node) => node.is_character_data(),
+                None => false,
+            };
+            let position = xml_item.nodes
+                                   .iter()
+                                   .position(&is_text)
+                                   .unwrap_or(xml_item.nodes.len());
+            xml_item.nodes.retain(|node| !is_text(node));
+            xml_item.nodes.insert(position, node);
+        }
+    }
+
+    pub fn get_nodes(rc: XmlItemRc) -> Vec<XmlItemRc> {
+        match *rc.borrow() {
+            Some(ref xml_item) => xml_item.nodes.clone(),
+            None => vec![],
+        }
     }
 
+    /// Returns the parent of a node, or None item if the node has no parent
+    /// or the parent is dropped already.
     pub fn get_parent(rc: XmlItemRc) -> XmlItemRc {
-        match &mut *rc.borrow_mut() {
-            &mut Some(ref mut xml_item) => {
-                return xml_item.parent.clone();
+        match *rc.borrow() {
+            Some(ref xml_item) => xml_item.parent.upgrade().unwrap_or_else(Self::get_empty_item),
+            None => Self::get_empty_item(),
+        }
+    }
+
+    fn is_character_data(&self) -> bool {
+        matches!(self.kind,
+                 XmlItemKind::Text | XmlItemKind::CData | XmlItemKind::EntityReference)
+    }
+
+    fn character_data(&self) -> String {
+        match self.kind {
+            XmlItemKind::EntityReference => format!("&{};", self.name),
+            _ => self.value.clone(),
+        }
+    }
+
+    pub fn get_declaration(rc: XmlItemRc) -> Option<XmlDeclaration> {
+        match *rc.borrow() {
+            Some(ref xml_item) => xml_item.declaration.clone(),
+            None => None,
+        }
+    }
+
+    /// Sets XML declaration, that is printed in front of the root element.
+    /// Pass None to print the document without declaration.
+    pub fn set_declaration(root: XmlItemRc, declaration: Option<XmlDeclaration>) {
+        if let Some(ref mut xml_item) = *root.borrow_mut() {
+            xml_item.declaration = declaration;
+            xml_item.declaration_markup = None;
+        }
+    }
+
+    /// Sets markup, that is printed instead of XML declaration of the document,
+    /// until the declaration is replaced.
+    pub fn set_declaration_markup(root: XmlItemRc, markup: Option<String>) {
+        if let Some(ref mut xml_item) = *root.borrow_mut() {
+            xml_item.declaration_markup = markup;
+        }
+    }
+
+    /// Returns document type declaration of the root element, or of the Doctype node itself.
+    pub fn get_doctype(rc: XmlItemRc) -> Option<XmlDoctype> {
+        match *rc.borrow() {
+            Some(ref xml_item) => {
+                if xml_item.kind == XmlItemKind::Doctype {
+                    return Some(xml_item.doctype());
+                }
+                for node in &xml_item.prolog {
+                    if let Some(ref node) = *node.borrow() {
+                        if node.kind == XmlItemKind::Doctype {
+                            return Some(node.doctype());
+                        }
+                    }
+                }
+                None
             }
-            _ => {
-                return Self::get_empty_item();
+            None => None,
+        }
+    }
+
+    /// Replaces document type declaration in the prolog of the root element.
+    /// New declaration goes in front of the prolog. Pass None to remove it.
+    pub fn set_doctype(root: XmlItemRc, doctype: Option<XmlDoctype>) {
+        if let Some(ref mut xml_item) = *root.borrow_mut() {
+            let position = xml_item.prolog
+                                   .iter()
+                                   .position(|node| Self::get_kind(node.clone()) == XmlItemKind::Doctype);
+            if let Some(position) = position {
+                xml_item.prolog.remove(position);
             }
-        };
+            if let Some(doctype) = doctype {
+                xml_item.prolog.insert(position.unwrap_or(0), Self::new_doctype(doctype));
+            }
+        }
+    }
+
+    fn doctype(&self) -> XmlDoctype {
+        xml_writer::doctype(self)
+    }
+
+    /// Returns nodes, that go in front of the root element.
+    pub fn get_prolog(rc: XmlItemRc) -> Vec<XmlItemRc> {
+        match *rc.borrow() {
+            Some(ref xml_item) => xml_item.prolog.clone(),
+            None => vec![],
+        }
+    }
+
+    /// Returns nodes, that go after the root element.
+    pub fn get_epilog(rc: XmlItemRc) -> Vec<XmlItemRc> {
+        match *rc.borrow() {
+            Some(ref xml_item) => xml_item.epilog.clone(),
+            None => vec![],
+        }
+    }
+
+    /// Puts a node in front of the root element. Such nodes have no parent.
+    pub fn add_prolog_node(root: XmlItemRc, node: XmlItemRc) -> XmlItemRc {
+        if let Some(ref mut xml_item) = *root.borrow_mut() {
+            xml_item.prolog.push(node.clone());
+        }
+        node
+    }
+
+    /// Puts a node after the root element. Such nodes have no parent.
+    pub fn add_epilog_node(root: XmlItemRc, node: XmlItemRc) -> XmlItemRc {
+        if let Some(ref mut xml_item) = *root.borrow_mut() {
+            xml_item.epilog.push(node.clone());
+        }
+        node
     }
 
     /// Creates a new unbound None.
@@ -148,57 +583,162 @@ impl XmlItem {
         self.nodes = vec![];
     }
 
-    /// Deletes all children of this node.
+    /// Deletes all children of this node. Dropping the node frees them anyway,
+    /// so it's needed only to free them while the node is still in use.
     pub fn clean(rc: XmlItemRc) {
         if let &mut Some(ref mut test) = &mut *rc.borrow_mut() {
             test.delete_nodes();
         }
     }
 
-    fn to_string(&mut self, offset: usize) -> String {
-        let mut result = format!("{}<{}", Self::get_tabs(&offset), self.name.clone());
-        for (key, value) in &self.attributes {
-            result = format!("{} {}=\"{}\"", result, key, value);
-        }
-        if self.nodes.is_empty() {
-            result = format!("{}/>", result);
-            return result;
+    /// Checks, that the item and it's child nodes can be written as XML.
+    /// as_string() prints them anyway, but a comment with `--` in it
+    /// is not read back the same. Problems are reported as Syntax errors
+    /// at the position the node was read from, if it was.
+    pub fn check(rc: XmlItemRc) -> Result<(), XmlError> {
+        Self::check_encoded(rc, XmlEncoding::Utf8)
+    }
+
+    /// Same as [check](#method.check), but also checks, that the encoding can represent
+    /// names, comments, processing instructions and DOCTYPE. Characters of text
+    /// and attribute values are written as character references, if it can't,
+    /// but references mean nothing anywhere else. Such problems are Encoding errors.
+    pub fn check_encoded(rc: XmlItemRc, encoding: XmlEncoding) -> Result<(), XmlError> {
+        match *rc.borrow() {
+            Some(ref xml_item) => {
+                for node in xml_item.prolog.iter().chain(&xml_item.epilog) {
+                    Self::check_encoded(node.clone(), encoding)?;
+                }
+                xml_item.check_nodes(encoding)
+            }
+            None => Ok(()),
         }
-        result = format!("{}>", result);
+    }
 
+    fn check_nodes(&self, encoding: XmlEncoding) -> Result<(), XmlError> {
+        let position = self.span.map(|span| span.start).unwrap_or_default();
+        // Original markup is printed as it was read.
+        if self.markup.is_none() {
+            if let Some(message) = self.problem() {
+                return Err(XmlError::Syntax { message, position });
+            }
+        }
+        if let Some(ch) = self.unencodable_char(encoding) {
+            return Err(XmlError::Encoding {
+                message: format!("{} can't represent '{}' outside of text and attribute values",
+                                 encoding.name(),
+                                 ch),
+                position,
+            });
+        }
         for node in &self.nodes {
-            if let &mut Some(ref mut xml_item) = &mut *node.borrow_mut() {
-                let node_result = xml_item.to_string(offset + 1);
-                result = format!("{}\n{}", result, node_result);
+            if let Some(ref node) = *node.borrow() {
+                node.check_nodes(encoding)?;
             }
         }
+        Ok(())
+    }
 
-        result = format!("{}\n{}</{}>",
-                         result,
-                         Self::get_tabs(&offset),
-                         self.name.clone());
-        return result;
+    /// Character of the name, attribute names or content, other than text,
+    /// that the encoding can't represent.
+    fn unencodable_char(&self, encoding: XmlEncoding) -> Option<char> {
+        let mut parts = vec![&self.name];
+        parts.extend(self.attributes.keys());
+        match self.kind {
+            XmlItemKind::Comment | XmlItemKind::ProcessingInstruction => parts.push(&self.value),
+            XmlItemKind::Doctype => {
+                parts.push(&self.value);
+                parts.extend(self.public_id.iter().chain(&self.system_id));
+            }
+            _ => {}
+        }
+        parts.iter()
+             .flat_map(|part| part.chars())
+             .find(|&ch| !encoding.can_encode(ch))
     }
 
-    #[allow(unused_variables)]
-    fn get_tabs(offset: &usize) -> String {
-        let mut result = "".to_string();
-        for x in 0..*offset {
-            result = format!("{}{}", result, "\t");
+    /// Why the node itself can't be written as XML.
+    fn problem(&self) -> Option<String> {
+        match self.kind {
+            XmlItemKind::Comment if self.value.contains("--") || self.value.ends_with('-') => {
+                Some("comment can't contain \"--\" or end with \"-\"".to_string())
+            }
+            XmlItemKind::ProcessingInstruction if !xml_escape::is_name(&self.name) => {
+                Some(format!("\"{}\" is not a valid processing instruction target", self.name))
+            }
+            // <?xml ...?> is the XML declaration, see set_declaration().
+            XmlItemKind::ProcessingInstruction if self.name.eq_ignore_ascii_case("xml") => {
+                Some(format!("processing instruction target \"{}\" is reserved", self.name))
+            }
+            XmlItemKind::ProcessingInstruction if self.value.contains("?>") => {
+                Some("processing instruction can't contain \"?>\"".to_string())
+            }
+            XmlItemKind::EntityReference if !xml_escape::is_name(&self.name) => {
+                Some(format!("\"{}\" is not a valid entity name", self.name))
+            }
+            XmlItemKind::Doctype if self.public_id.is_some() && self.system_id.is_none() => {
+                Some("DOCTYPE with a public identifier needs a system identifier".to_string())
+            }
+            _ => None,
         }
-        result
     }
 
+
     /// Prints item and it's child nodes into a String in a human-readable form.
+    /// The root element is printed together with it's declaration, prolog and epilog.
     pub fn as_string(rc: XmlItemRc) -> String {
-        match &mut *rc.borrow_mut() {
-            &mut Some(ref mut xml_item) => {
-                return xml_item.to_string(0);
-            }
-            _ => {
-                return "".to_string();
+        Self::as_string_with(rc, &WriterOptions::default())
+    }
+
+    /// Same as [as_string](#method.as_string), but prints the way options say.
+    pub fn as_string_with(rc: XmlItemRc, options: &WriterOptions) -> String {
+        xml_writer::write_item(rc, None, XmlDocumentWriter::new(options, XmlEncoding::Utf8))
+    }
+}
+
+impl XmlWriterNode for XmlItem {
+    fn kind(&self) -> XmlItemKind {
+        self.kind
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn value(&self) -> &str {
+        &self.value
+    }
+
+    fn attributes(&self) -> &XmlAttributes {
+        &self.attributes
+    }
+
+    fn public_id(&self) -> Option<&String> {
+        self.public_id.as_ref()
+    }
+
+    fn system_id(&self) -> Option<&String> {
+        self.system_id.as_ref()
+    }
+
+    fn markup(&self) -> Option<&String> {
+        self.markup.as_ref()
+    }
+
+    fn end_markup(&self) -> Option<&String> {
+        self.end_markup.as_ref()
+    }
+
+    fn child_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    fn for_each_child(&self, f: &mut dyn FnMut(&XmlItem)) {
+        for node in &self.nodes {
+            if let Some(ref node) = *node.borrow() {
+                f(node);
             }
-        };
+        }
     }
 }
 
@@ -215,7 +755,8 @@ impl XmlItem {
 
 #[cfg(test)]
 mod test {
-    use {XmlItem, XmlItemRc};
+    use super::Rc;
+    use {XmlDeclaration, XmlDoctype, XmlItem, XmlItemKind, XmlItemRc, XmlPosition, XmlSpan};
 
     #[test]
     fn cascade_destruction() {
@@ -258,6 +799,151 @@ mod test {
         }
     }
 
+    #[test]
+    fn text() {
+        let root: XmlItemRc = XmlItem::new("Root".to_string());
+        let title = XmlItem::add_node(root.clone(), XmlItem::new("Title".to_string()));
+        XmlItem::add_text(title.clone(), "Hello".to_string());
+        assert_eq!(XmlItem::get_text(title.clone()), "Hello".to_string());
+        assert_eq!(XmlItem::as_string(title.clone()),
+                   "<Title>Hello</Title>".to_string());
+
+        XmlItem::add_text(root.clone(), "a".to_string());
+        XmlItem::add_node(root.clone(), XmlItem::new("Node".to_string()));
+        XmlItem::add_text(root.clone(), "b".to_string());
+        assert_eq!(XmlItem::get_text(root.clone()), "ab".to_string());
+
+        XmlItem::set_text(root.clone(), "c".to_string());
+        let nodes = XmlItem::get_nodes(root.clone());
+        assert_eq!(nodes.len(), 3);
+        assert_eq!(XmlItem::get_kind(nodes[1].clone()), XmlItemKind::Text);
+        assert_eq!(XmlItem::get_text(nodes[1].clone()), "c".to_string());
+        assert_eq!(XmlItem::as_string(root.clone()),
+                   "<Root><Title>Hello</Title>c<Node/></Root>".to_string());
+    }
+
+    #[test]
+    fn comments() {
+        let root: XmlItemRc = XmlItem::new("Root".to_string());
+        XmlItem::add_prolog_node(root.clone(), XmlItem::new_comment(" head ".to_string()));
+        XmlItem::add_node(root.clone(), XmlItem::new_comment(" body ".to_string()));
+        XmlItem::add_epilog_node(root.clone(), XmlItem::new_comment(" tail ".to_string()));
+
+        assert_eq!(XmlItem::get_text(XmlItem::get_prolog(root.clone())[0].clone()),
+                   " head ".to_string());
+        assert_eq!(XmlItem::as_string(root.clone()),
+                   "<!-- head -->\n<Root>\n\t<!-- body -->\n</Root>\n<!-- tail -->".to_string());
+        assert!(XmlItem::check(root.clone()).is_ok());
+
+        let body = XmlItem::add_node(root.clone(), XmlItem::new_comment("a -- b".to_string()));
+        assert!(XmlItem::check(root.clone()).is_err());
+        XmlItem::set_text(body.clone(), "a -".to_string());
+        assert!(XmlItem::check(root.clone()).is_err());
+        XmlItem::set_text(body.clone(), "a - b".to_string());
+        XmlItem::add_epilog_node(root.clone(), XmlItem::new_comment("-->".to_string()));
+        assert!(XmlItem::check(root.clone()).is_err());
+    }
+
+    #[test]
+    fn cdata() {
+        let root: XmlItemRc = XmlItem::new("Script".to_string());
+        XmlItem::add_text(root.clone(), "a".to_string());
+        let cdata = XmlItem::add_node(root.clone(), XmlItem::new_cdata(" x]]>y ".to_string()));
+
+        assert_eq!(XmlItem::get_kind(cdata.clone()), XmlItemKind::CData);
+        assert_eq!(XmlItem::get_text(root.clone()), "a x]]>y ".to_string());
+        assert_eq!(XmlItem::as_string(root.clone()),
+                   "<Script>a<![CDATA[ x]]]]><![CDATA[>y ]]></Script>".to_string());
+    }
+
+    #[test]
+    fn processing_instructions() {
+        let root: XmlItemRc = XmlItem::new("Root".to_string());
+        XmlItem::add_prolog_node(root.clone(),
+                                 XmlItem::new_processing_instruction("xml-stylesheet".to_string(),
+                                                                     "href=\"a.xsl\"".to_string()));
+        let pi = XmlItem::add_node(root.clone(),
+                                   XmlItem::new_processing_instruction("flush".to_string(),
+                                                                       "".to_string()));
+
+        assert_eq!(XmlItem::get_kind(pi.clone()), XmlItemKind::ProcessingInstruction);
+        assert_eq!(XmlItem::get_name(pi.clone()), "flush".to_string());
+        assert_eq!(XmlItem::as_string(root.clone()),
+                   "<?xml-stylesheet href=\"a.xsl\"?>\n<Root>\n\t<?flush?>\n</Root>".to_string());
+        assert!(XmlItem::check(root.clone()).is_ok());
+
+        XmlItem::set_text(pi.clone(), "a ?> b".to_string());
+        assert!(XmlItem::check(root.clone()).is_err());
+        XmlItem::set_text(pi.clone(), "".to_string());
+        for target in &["XML", "two words", "", "1st"] {
+            let pi = XmlItem::new_processing_instruction(target.to_string(), "".to_string());
+            assert!(XmlItem::check(pi).is_err());
+        }
+        assert!(XmlItem::check(root.clone()).is_ok());
+    }
+
+    #[test]
+    fn declaration() {
+        let root: XmlItemRc = XmlItem::new("Root".to_string());
+        let mut declaration = XmlDeclaration::new("1.0".to_string());
+        declaration.encoding = Some("UTF-8".to_string());
+        declaration.standalone = Some(true);
+        XmlItem::set_declaration(root.clone(), Some(declaration.clone()));
+
+        assert_eq!(XmlItem::get_declaration(root.clone()), Some(declaration));
+        assert_eq!(XmlItem::as_string(root.clone()),
+                   "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n<Root/>"
+                       .to_string());
+
+        XmlItem::set_declaration(root.clone(), None);
+        assert_eq!(XmlItem::as_string(root.clone()), "<Root/>".to_string());
+    }
+
+    #[test]
+    fn doctype() {
+        let root: XmlItemRc = XmlItem::new("Root".to_string());
+        XmlItem::add_prolog_node(root.clone(), XmlItem::new_comment(" head ".to_string()));
+        let mut doctype = XmlDoctype::new("Root".to_string());
+        doctype.system_id = Some("root.dtd".to_string());
+        XmlItem::set_doctype(root.clone(), Some(doctype.clone()));
+        assert_eq!(XmlItem::get_doctype(root.clone()), Some(doctype.clone()));
+        assert_eq!(XmlItem::as_string(root.clone()),
+                   "<!DOCTYPE Root SYSTEM \"root.dtd\">\n<!-- head -->\n<Root/>".to_string());
+
+        doctype.public_id = Some("-//SGL//\"Root\"//EN".to_string());
+        doctype.internal_subset = Some(" <!ENTITY a \"b\"> ".to_string());
+        XmlItem::set_doctype(root.clone(), Some(doctype.clone()));
+        assert_eq!(XmlItem::get_prolog(root.clone()).len(), 2);
+        assert_eq!(doctype.entities(), vec!["a".to_string()]);
+        assert_eq!(doctype.as_string(),
+                   "<!DOCTYPE Root PUBLIC '-//SGL//\"Root\"//EN' \"root.dtd\" [ <!ENTITY a \"b\"> ]>"
+                       .to_string());
+
+        assert!(XmlItem::check(root.clone()).is_ok());
+        let prolog = XmlItem::get_prolog(root.clone());
+        assert_eq!(XmlItem::get_kind(prolog[0].clone()), XmlItemKind::Doctype);
+        assert!(prolog[0].borrow().as_ref().unwrap().attributes.is_empty());
+
+        doctype.system_id = None;
+        XmlItem::set_doctype(root.clone(), Some(doctype.clone()));
+        assert!(XmlItem::check(root.clone()).is_err());
+
+        XmlItem::set_doctype(root.clone(), None);
+        assert_eq!(XmlItem::get_doctype(root.clone()), None);
+    }
+
+    #[test]
+    fn escaping() {
+        let root: XmlItemRc = XmlItem::new("Root".to_string());
+        XmlItem::set_attribute(root.clone(), "a".to_string(), "\"x\" & <y>".to_string());
+        XmlItem::add_text(root.clone(), "1 < 2 & 3 > 2 \"'".to_string());
+        XmlItem::add_node(root.clone(), XmlItem::new_cdata("<&>".to_string()));
+        assert_eq!(XmlItem::as_string(root.clone()),
+                   "<Root a=\"&quot;x&quot; &amp; &lt;y>\">\
+                    1 &lt; 2 &amp; 3 &gt; 2 \"'<![CDATA[<&>]]></Root>"
+                       .to_string());
+    }
+
     #[test]
     fn parents() {
         let root: XmlItemRc = XmlItem::new("Root".to_string());
@@ -277,4 +963,59 @@ mod test {
         assert_eq!(god.borrow_mut().is_none(), true); // There is no God.
     }
 
+    #[test]
+    fn spans() {
+        let root: XmlItemRc = XmlItem::new("Root".to_string());
+        assert_eq!(XmlItem::get_span(root.clone()), None);
+        assert_eq!(XmlItem::get_attribute_span(root.clone(), "a"), None);
+
+        let span = XmlSpan::new(XmlPosition::new(1, 1, 0), XmlPosition::new(2, 8, 20));
+        XmlItem::set_span(root.clone(), Some(span));
+        XmlItem::set_attribute_span(root.clone(), "a".to_string(), span);
+        assert_eq!(XmlItem::get_span(root.clone()), Some(span));
+        assert_eq!(XmlItem::get_attribute_span(root.clone(), "a"), Some(span));
+    }
+
+    #[test]
+    fn markup() {
+        let root: XmlItemRc = XmlItem::new("Root".to_string());
+        XmlItem::set_markup(root.clone(), Some("<Root\n>".to_string()));
+        XmlItem::set_end_markup(root.clone(), Some("</Root >".to_string()));
+        let text = XmlItem::add_text(root.clone(), "a & b".to_string());
+        XmlItem::set_markup(text.clone(), Some("a &amp; b".to_string()));
+        XmlItem::add_node(root.clone(), XmlItem::new("New".to_string()));
+        assert_eq!(XmlItem::as_string(root.clone()),
+                   "<Root\n>a &amp; b<New/></Root >".to_string());
+
+        XmlItem::set_attribute(root.clone(), "x".to_string(), "1".to_string());
+        XmlItem::set_text(text.clone(), "c".to_string());
+        assert_eq!(XmlItem::get_markup(root.clone()), None);
+        assert_eq!(XmlItem::as_string(root.clone()),
+                   "<Root x=\"1\">c<New/></Root >".to_string());
+
+        // Empty element gets both tags anew, once it has nodes.
+        let empty: XmlItemRc = XmlItem::new("Empty".to_string());
+        XmlItem::set_markup(empty.clone(), Some("<Empty />".to_string()));
+        XmlItem::set_end_markup(empty.clone(), Some("".to_string()));
+        assert_eq!(XmlItem::as_string(empty.clone()), "<Empty />".to_string());
+        XmlItem::add_text(empty.clone(), "t".to_string());
+        assert_eq!(XmlItem::as_string(empty.clone()), "<Empty>t</Empty>".to_string());
+    }
+
+    #[test]
+    fn weak_parents() {
+        let root: XmlItemRc = XmlItem::new("Root".to_string());
+        let node_1 = XmlItem::add_node(root.clone(), XmlItem::new("Root's One".to_string()));
+        let node_2 = XmlItem::add_node(node_1.clone(), XmlItem::new("One's Two".to_string()));
+        let weak_root = Rc::downgrade(&root);
+        let weak_node_1 = Rc::downgrade(&node_1);
+        drop(node_1);
+        assert!(weak_node_1.upgrade().is_some()); // Root still owns it.
+
+        drop(root);
+        assert!(weak_root.upgrade().is_none());
+        assert!(weak_node_1.upgrade().is_none());
+        assert!(XmlItem::get_parent(node_2.clone()).borrow().is_none());
+        assert_eq!(XmlItem::get_name(node_2), "One's Two".to_string());
+    }
 }
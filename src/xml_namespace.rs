@@ -0,0 +1,287 @@
+// Licensed to the Square Gears Logic (SGL) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The SGL licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+// http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use xml_item::{XmlItem, XmlItemKind, XmlItemRc};
+
+/// Namespace bound to the "xml" prefix by definition.
+pub const XML_NAMESPACE: &str = "http://www.w3.org/XML/1998/namespace";
+/// Namespace of the "xmlns" attributes.
+pub const XMLNS_NAMESPACE: &str = "http://www.w3.org/2000/xmlns/";
+
+/// Qualified name of an element or attribute, such as `svg:rect`.
+///
+/// XmlItem keeps names as plain strings, so QName is built on request
+/// with namespace resolved against `xmlns` declarations in scope.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct XmlQName {
+    pub prefix: Option<String>,
+    pub local_name: String,
+    /// None for names without namespace.
+    pub namespace_uri: Option<String>,
+}
+
+impl XmlQName {
+    /// Splits `prefix:local_name`. Namespace is left unresolved.
+    pub fn parse(name: &str) -> XmlQName {
+        match name.find(':') {
+            Some(pos) => {
+                XmlQName {
+                    prefix: Some(name[..pos].to_string()),
+                    local_name: name[pos + 1..].to_string(),
+                    namespace_uri: None,
+                }
+            }
+            None => {
+                XmlQName {
+                    prefix: None,
+                    local_name: name.to_string(),
+                    namespace_uri: None,
+                }
+            }
+        }
+    }
+
+    /// Returns `prefix:local_name`, or just local name.
+    pub fn as_string(&self) -> String {
+        match self.prefix {
+            Some(ref prefix) => format!("{}:{}", prefix, self.local_name),
+            None => self.local_name.clone(),
+        }
+    }
+}
+
+impl XmlItem {
+    /// Returns element name split to prefix and local name, with namespace resolved.
+    /// Unprefixed elements get default namespace, set by `xmlns` attribute.
+    pub fn get_qname(rc: XmlItemRc) -> XmlQName {
+        let mut result = XmlQName::parse(&Self::get_name(rc.clone()));
+        if Self::get_kind(rc.clone()) == XmlItemKind::Element {
+            result.namespace_uri = Self::lookup_namespace_uri(rc, result.prefix.as_deref());
+        }
+        result
+    }
+
+    pub fn get_prefix(rc: XmlItemRc) -> Option<String> {
+        XmlQName::parse(&Self::get_name(rc)).prefix
+    }
+
+    pub fn get_local_name(rc: XmlItemRc) -> String {
+        XmlQName::parse(&Self::get_name(rc)).local_name
+    }
+
+    pub fn get_namespace_uri(rc: XmlItemRc) -> Option<String> {
+        Self::get_qname(rc).namespace_uri
+    }
+
+    /// Returns attribute name split to prefix and local name, with namespace resolved,
+    /// or None if there is no such attribute.
+    /// Default namespace is never applied to attributes.
+    pub fn get_attribute_qname(rc: XmlItemRc, attribute: &str) -> Option<XmlQName> {
+        let exists = match *rc.borrow() {
+            Some(ref xml_item) => xml_item.attributes.contains_key(attribute),
+            None => false,
+        };
+        if !exists {
+            return None;
+        }
+
+        let mut result = XmlQName::parse(attribute);
+        result.namespace_uri = match result.prefix {
+            Some(ref prefix) if prefix == "xmlns" => Some(XMLNS_NAMESPACE.to_string()),
+            Some(ref prefix) => Self::lookup_namespace_uri(rc, Some(prefix)),
+            None if attribute == "xmlns" => Some(XMLNS_NAMESPACE.to_string()),
+            None => None,
+        };
+        Some(result)
+    }
+
+    /// Finds value of an attribute by it's namespace and local name, whatever prefix it has.
+    pub fn get_attribute_ns(rc: XmlItemRc,
+                            namespace_uri: &str,
+                            local_name: &str)
+                            -> Option<String> {
+        let attributes = match *rc.borrow() {
+            Some(ref xml_item) => xml_item.attributes.clone(),
+            None => return None,
+        };
+        for (name, value) in attributes {
+            if let Some(qname) = Self::get_attribute_qname(rc.clone(), &name) {
+                if qname.local_name == local_name &&
+                   qname.namespace_uri.as_deref() == Some(namespace_uri) {
+                    return Some(value);
+                }
+            }
+        }
+        None
+    }
+
+    /// Finds namespace bound to a prefix at this node, looking through `xmlns:prefix`
+    /// attributes of the node and it's parents. Pass None to get default namespace.
+    pub fn lookup_namespace_uri(rc: XmlItemRc, prefix: Option<&str>) -> Option<String> {
+        let attribute = match prefix {
+            Some("xml") => return Some(XML_NAMESPACE.to_string()),
+            Some("xmlns") => return Some(XMLNS_NAMESPACE.to_string()),
+            Some(prefix) => format!("xmlns:{}", prefix),
+            None => "xmlns".to_string(),
+        };
+
+        let mut node = rc;
+        loop {
+            let parent = match *node.borrow() {
+                Some(ref xml_item) => {
+                    if let Some(uri) = xml_item.attributes.get(&attribute) {
+                        // xmlns="" cancels default namespace.
+                        if uri.is_empty() {
+                            return None;
+                        }
+                        return Some(uri.clone());
+                    }
+                    XmlItem::get_parent(node.clone())
+                }
+                None => return None,
+            };
+            node = parent;
+        }
+    }
+
+    /// Finds a prefix bound to a namespace at this node. Returns None if namespace
+    /// is not declared, or if it is declared only as default one.
+    pub fn lookup_prefix(rc: XmlItemRc, namespace_uri: &str) -> Option<String> {
+        let mut node = rc.clone();
+        loop {
+            let mut prefixes = vec![];
+            let parent = match *node.borrow() {
+                Some(ref xml_item) => {
+                    for (name, value) in &xml_item.attributes {
+                        if let Some(prefix) = name.strip_prefix("xmlns:") {
+                            if value == namespace_uri {
+                                prefixes.push(prefix.to_string());
+                            }
+                        }
+                    }
+                    XmlItem::get_parent(node.clone())
+                }
+                None => return None,
+            };
+
+            // Prefix may be redeclared closer to the node.
+            prefixes.sort();
+            for prefix in prefixes {
+                if Self::lookup_namespace_uri(rc.clone(), Some(&prefix)).as_deref() ==
+                   Some(namespace_uri) {
+                    return Some(prefix);
+                }
+            }
+            node = parent;
+        }
+    }
+
+    /// Returns prefixes of the element and it's attributes, that are not bound to any namespace.
+    pub fn get_unbound_prefixes(rc: XmlItemRc) -> Vec<String> {
+        let mut names = vec![Self::get_name(rc.clone())];
+        if let Some(ref xml_item) = *rc.borrow() {
+            names.extend(xml_item.attributes.keys().cloned());
+        }
+
+        let mut result = vec![];
+        for name in names {
+            if let Some(prefix) = XmlQName::parse(&name).prefix {
+                if prefix != "xmlns" && !result.contains(&prefix) &&
+                   Self::lookup_namespace_uri(rc.clone(), Some(&prefix)).is_none() {
+                    result.push(prefix);
+                }
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{XmlQName, XMLNS_NAMESPACE, XML_NAMESPACE};
+    use {XmlItem, XmlItemRc};
+
+    const SVG: &str = "http://www.w3.org/2000/svg";
+    const XLINK: &str = "http://www.w3.org/1999/xlink";
+
+    fn svg() -> (XmlItemRc, XmlItemRc) {
+        let root: XmlItemRc = XmlItem::new("svg:svg".to_string());
+        XmlItem::set_attribute(root.clone(), "xmlns:svg".to_string(), SVG.to_string());
+        XmlItem::set_attribute(root.clone(), "xmlns:xlink".to_string(), XLINK.to_string());
+        let rect = XmlItem::add_node(root.clone(), XmlItem::new("svg:rect".to_string()));
+        XmlItem::set_attribute(rect.clone(), "xlink:href".to_string(), "#a".to_string());
+        XmlItem::set_attribute(rect.clone(), "width".to_string(), "1".to_string());
+        (root, rect)
+    }
+
+    #[test]
+    fn qnames() {
+        let (root, rect) = svg();
+        assert_eq!(XmlItem::get_qname(rect.clone()),
+                   XmlQName {
+                       prefix: Some("svg".to_string()),
+                       local_name: "rect".to_string(),
+                       namespace_uri: Some(SVG.to_string()),
+                   });
+        assert_eq!(XmlItem::get_local_name(root.clone()), "svg".to_string());
+        assert_eq!(XmlItem::get_attribute_qname(rect.clone(), "xlink:href")
+                       .unwrap()
+                       .namespace_uri,
+                   Some(XLINK.to_string()));
+        assert_eq!(XmlItem::get_attribute_qname(rect.clone(), "width")
+                       .unwrap()
+                       .namespace_uri,
+                   None);
+        assert_eq!(XmlItem::get_attribute_qname(root.clone(), "xmlns:svg")
+                       .unwrap()
+                       .namespace_uri,
+                   Some(XMLNS_NAMESPACE.to_string()));
+        assert_eq!(XmlItem::get_attribute_qname(rect.clone(), "height"), None);
+        assert_eq!(XmlItem::get_attribute_ns(rect.clone(), XLINK, "href"),
+                   Some("#a".to_string()));
+        assert_eq!(XmlQName::parse("a:b").as_string(), "a:b".to_string());
+    }
+
+    #[test]
+    fn default_namespace() {
+        let root: XmlItemRc = XmlItem::new("Envelope".to_string());
+        XmlItem::set_attribute(root.clone(), "xmlns".to_string(), "urn:a".to_string());
+        let body = XmlItem::add_node(root.clone(), XmlItem::new("Body".to_string()));
+        let plain = XmlItem::add_node(body.clone(), XmlItem::new("Plain".to_string()));
+        XmlItem::set_attribute(plain.clone(), "xmlns".to_string(), "".to_string());
+
+        assert_eq!(XmlItem::get_namespace_uri(body.clone()), Some("urn:a".to_string()));
+        assert_eq!(XmlItem::get_namespace_uri(plain.clone()), None);
+        assert_eq!(XmlItem::lookup_namespace_uri(plain.clone(), Some("xml")),
+                   Some(XML_NAMESPACE.to_string()));
+    }
+
+    #[test]
+    fn lookups() {
+        let (root, rect) = svg();
+        assert_eq!(XmlItem::lookup_namespace_uri(rect.clone(), Some("svg")),
+                   Some(SVG.to_string()));
+        assert_eq!(XmlItem::lookup_namespace_uri(rect.clone(), Some("x")), None);
+        assert_eq!(XmlItem::lookup_prefix(rect.clone(), XLINK), Some("xlink".to_string()));
+
+        // Redeclared prefix hides the outer declaration.
+        XmlItem::set_attribute(rect.clone(), "xmlns:xlink".to_string(), "urn:b".to_string());
+        assert_eq!(XmlItem::lookup_prefix(rect.clone(), XLINK), None);
+        assert_eq!(XmlItem::lookup_prefix(root.clone(), XLINK), Some("xlink".to_string()));
+
+        XmlItem::set_attribute(rect.clone(), "x:y".to_string(), "".to_string());
+        assert_eq!(XmlItem::get_unbound_prefixes(rect.clone()), vec!["x".to_string()]);
+    }
+}
@@ -0,0 +1,200 @@
+// Licensed to the Square Gears Logic (SGL) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The SGL licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+// http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+/// The highest max_depth. Trees are written, checked and dropped recursively,
+/// so deeper ones could overflow the stack of a thread.
+pub const MAX_DEPTH_LIMIT: usize = 512;
+
+/// Dialect of XML accepted by [XmlDom](struct.XmlDom.html).
+///
+/// Default options are lenient and read old SglXml files as before.
+/// Use [strict](#method.strict) to accept only well-formed XML 1.0,
+/// or tune single toggles:
+///
+/// ```
+/// use libsgl_xml::ParserOptions;
+///
+/// let options = ParserOptions::strict().set_legacy_comments(true);
+/// assert!(!options.backslash_quotes);
+/// ```
+///
+/// With `recover` set, problems in the markup are collected as diagnostics instead
+/// of stopping the parser, see [XmlDom::open_recovering](struct.XmlDom.html#method.open_recovering).
+///
+/// Limits protect against hostile documents. Defaults fit most of real documents,
+/// exceeding a limit is reported as [XmlError::LimitExceeded](enum.XmlError.html).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParserOptions {
+    /// Attribute values may contain slashed quotes `\"`.
+    pub backslash_quotes: bool,
+    /// Comments may start with `<--` as well as with `<!--`.
+    pub legacy_comments: bool,
+    /// Leading and trailing whitespace of text is dropped,
+    /// so text of nothing but whitespace disappears.
+    pub trim_whitespace: bool,
+    /// Attribute may be repeated in a tag, the last value wins.
+    pub duplicate_attributes: bool,
+    /// Text, other than whitespace, CDATA sections and entity references may appear
+    /// outside of the root element. They are dropped.
+    pub text_outside_root: bool,
+    /// Names may use namespace prefixes, that are not declared, or may be malformed
+    /// qualified names, such as `a:b:c`.
+    pub unbound_prefixes: bool,
+    /// Text and attribute values may refer to entities, that are neither predefined
+    /// nor declared by the internal subset of DOCTYPE. References to entities,
+    /// that are not predefined, are kept unexpanded anyway.
+    pub undeclared_entities: bool,
+    /// Names, text, attribute values and comments may have characters, that XML
+    /// doesn't allow there: names starting with a digit, `&` outside of references,
+    /// `<` in attribute values, `]]>` in text, `--` in comments and control characters.
+    pub loose_characters: bool,
+    /// Malformed markup is skipped or repaired and reported as a diagnostic,
+    /// instead of being an error. Exceeded limits, I/O and encoding errors still are.
+    pub recover: bool,
+    /// Original markup of every node is kept, so an unchanged document is saved
+    /// exactly as it was read. Whitespace is never trimmed then.
+    pub preserve_formatting: bool,
+    /// How deep elements may be nested. The root element is at depth 1.
+    /// Values above 512 are taken as 512.
+    pub max_depth: usize,
+    /// How many attributes a tag may have.
+    pub max_attributes: usize,
+    /// Longest name of element, attribute or processing instruction, in bytes.
+    pub max_name_length: usize,
+    /// Longest text, attribute value, comment, CDATA section and so on, in bytes.
+    pub max_text_length: usize,
+    /// How many nodes a DOM, or a subtree built by
+    /// [XmlSubtreeBuilder](struct.XmlSubtreeBuilder.html), may have.
+    pub max_nodes: usize,
+}
+
+impl ParserOptions {
+    /// The current SglXml dialect.
+    pub fn lenient() -> ParserOptions {
+        ParserOptions {
+            backslash_quotes: true,
+            legacy_comments: true,
+            trim_whitespace: true,
+            duplicate_attributes: true,
+            text_outside_root: true,
+            unbound_prefixes: true,
+            undeclared_entities: true,
+            loose_characters: true,
+            recover: false,
+            preserve_formatting: false,
+            max_depth: 256,
+            max_attributes: 1024,
+            max_name_length: 1024,
+            max_text_length: 16 * 1024 * 1024,
+            max_nodes: 10_000_000,
+        }
+    }
+
+    /// XML 1.0 well-formedness.
+    pub fn strict() -> ParserOptions {
+        ParserOptions {
+            backslash_quotes: false,
+            legacy_comments: false,
+            trim_whitespace: false,
+            duplicate_attributes: false,
+            text_outside_root: false,
+            unbound_prefixes: false,
+            undeclared_entities: false,
+            loose_characters: false,
+            ..Self::lenient()
+        }
+    }
+
+    pub fn set_backslash_quotes(mut self, value: bool) -> ParserOptions {
+        self.backslash_quotes = value;
+        self
+    }
+
+    pub fn set_legacy_comments(mut self, value: bool) -> ParserOptions {
+        self.legacy_comments = value;
+        self
+    }
+
+    pub fn set_trim_whitespace(mut self, value: bool) -> ParserOptions {
+        self.trim_whitespace = value;
+        self
+    }
+
+    pub fn set_duplicate_attributes(mut self, value: bool) -> ParserOptions {
+        self.duplicate_attributes = value;
+        self
+    }
+
+    pub fn set_text_outside_root(mut self, value: bool) -> ParserOptions {
+        self.text_outside_root = value;
+        self
+    }
+
+    pub fn set_unbound_prefixes(mut self, value: bool) -> ParserOptions {
+        self.unbound_prefixes = value;
+        self
+    }
+
+    pub fn set_undeclared_entities(mut self, value: bool) -> ParserOptions {
+        self.undeclared_entities = value;
+        self
+    }
+
+    pub fn set_loose_characters(mut self, value: bool) -> ParserOptions {
+        self.loose_characters = value;
+        self
+    }
+
+    pub fn set_recover(mut self, value: bool) -> ParserOptions {
+        self.recover = value;
+        self
+    }
+
+    pub fn set_preserve_formatting(mut self, value: bool) -> ParserOptions {
+        self.preserve_formatting = value;
+        self
+    }
+
+    pub fn set_max_depth(mut self, value: usize) -> ParserOptions {
+        self.max_depth = value.min(MAX_DEPTH_LIMIT);
+        self
+    }
+
+    pub fn set_max_attributes(mut self, value: usize) -> ParserOptions {
+        self.max_attributes = value;
+        self
+    }
+
+    pub fn set_max_name_length(mut self, value: usize) -> ParserOptions {
+        self.max_name_length = value;
+        self
+    }
+
+    pub fn set_max_text_length(mut self, value: usize) -> ParserOptions {
+        self.max_text_length = value;
+        self
+    }
+
+    pub fn set_max_nodes(mut self, value: usize) -> ParserOptions {
+        self.max_nodes = value;
+        self
+    }
+}
+
+impl Default for ParserOptions {
+    fn default() -> ParserOptions {
+        ParserOptions::lenient()
+    }
+}
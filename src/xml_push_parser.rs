@@ -0,0 +1,181 @@
+// Licensed to the Square Gears Logic (SGL) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The SGL licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+// http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use xml_encoding::{XmlDecoder, XmlEncoding};
+use xml_error::XmlError;
+use xml_parser_options::ParserOptions;
+use xml_reader::{XmlEvent, XmlEventParser};
+
+/// Parser, that is fed with bytes as they arrive, such as frames of a network stream.
+/// Chunks may split tags or even characters at any byte, unfinished markup
+/// is kept till the next chunk:
+///
+/// ```
+/// use libsgl_xml::{XmlEvent, XmlPushParser};
+///
+/// let mut parser = XmlPushParser::new();
+/// let mut events = parser.feed(b"<stream><msg>Hel").unwrap();
+/// assert_eq!(events.len(), 2);
+/// events.extend(parser.feed(b"lo</msg></str").unwrap());
+/// events.extend(parser.feed(b"eam>").unwrap());
+/// events.extend(parser.finish().unwrap());
+/// match events[2] {
+///     XmlEvent::Text { ref text, .. } => assert_eq!(text, "Hello"),
+///     _ => panic!(),
+/// }
+/// ```
+///
+/// Use [XmlSubtreeBuilder](struct.XmlSubtreeBuilder.html) to collect the events
+/// into elements. Once an error is returned, the parser can't go on.
+pub struct XmlPushParser {
+    decoder: XmlDecoder,
+    parser: XmlEventParser,
+}
+
+impl XmlPushParser {
+    pub fn new() -> XmlPushParser {
+        Self::with_options(&ParserOptions::default())
+    }
+
+    pub fn with_options(options: &ParserOptions) -> XmlPushParser {
+        XmlPushParser {
+            decoder: XmlDecoder::new(),
+            parser: XmlEventParser::new(options.clone()),
+        }
+    }
+
+    /// Encoding of the document, once enough of it is fed to detect one.
+    pub fn encoding(&self) -> Option<XmlEncoding> {
+        self.decoder.encoding()
+    }
+
+    /// Problems skipped or repaired since the last call, when options have `recover` set.
+    pub fn take_diagnostics(&mut self) -> Vec<XmlError> {
+        self.parser.take_diagnostics()
+    }
+
+    /// Takes the next chunk of the document and returns events, that are complete now.
+    pub fn feed(&mut self, bytes: &[u8]) -> Result<Vec<XmlEvent>, XmlError> {
+        let text = self.decoder.decode(bytes, false)?;
+        self.parser.feed(&text);
+        self.collect_events()
+    }
+
+    /// Tells that the document is over and returns the rest of events,
+    /// the last one is EndDocument.
+    pub fn finish(&mut self) -> Result<Vec<XmlEvent>, XmlError> {
+        let text = self.decoder.decode(&[], true)?;
+        self.parser.feed(&text);
+        self.parser.finish();
+        self.collect_events()
+    }
+
+    fn collect_events(&mut self) -> Result<Vec<XmlEvent>, XmlError> {
+        let mut result = vec![];
+        while let Some(event) = self.parser.next_event()? {
+            result.push(event);
+        }
+        Ok(result)
+    }
+}
+
+impl Default for XmlPushParser {
+    fn default() -> XmlPushParser {
+        XmlPushParser::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::XmlPushParser;
+    use xml_dom::XmlSubtreeBuilder;
+    use xml_error::XmlError;
+    use xml_item::XmlItem;
+    use xml_parser_options::ParserOptions;
+    use xml_reader::{XmlEvent, XmlReader};
+
+    #[test]
+    fn any_chunks() {
+        let text = "<?xml version=\"1.0\"?>\n<a x=\"\u{263A}\">t&amp;t<![CDATA[<>]]><b/></a><!--e-->";
+        let whole = XmlReader::from_text(text, &ParserOptions::default())
+                        .map(|event| event.unwrap())
+                        .collect::<Vec<_>>();
+        for size in 1..text.len() {
+            let mut parser = XmlPushParser::new();
+            let mut events = vec![];
+            for chunk in text.as_bytes().chunks(size) {
+                events.extend(parser.feed(chunk).unwrap());
+            }
+            events.extend(parser.finish().unwrap());
+            assert_eq!(events, whole);
+        }
+    }
+
+    #[test]
+    fn doctype_chunks() {
+        let texts = ["<!DOCTYPE a [<!ENTITY x \"]>\"><!-- ] > -->]><a>&x;</a>",
+                     "<!DOCTYPE a SYSTEM \"a[b\"><a>&x;</a>"];
+        for text in &texts {
+            let whole = XmlReader::from_text(text, &ParserOptions::default())
+                            .map(|event| event.unwrap())
+                            .collect::<Vec<_>>();
+            for size in 1..text.len() {
+                let mut parser = XmlPushParser::new();
+                let mut events = vec![];
+                for chunk in text.as_bytes().chunks(size) {
+                    events.extend(parser.feed(chunk).unwrap());
+                }
+                // Everything, but the end of the document, comes without finish().
+                assert_eq!(events.len(), whole.len() - 1);
+                events.extend(parser.finish().unwrap());
+                assert_eq!(events, whole);
+            }
+        }
+    }
+
+    #[test]
+    fn subtrees() {
+        let mut parser = XmlPushParser::new();
+        let mut builder = XmlSubtreeBuilder::new(1);
+        let mut messages = vec![];
+        for chunk in &["<stream><msg id='1'>a<b>c</b", "></msg><msg id='2'/>", "</stream>"] {
+            for event in parser.feed(chunk.as_bytes()).unwrap() {
+                if let Some(item) = builder.add_event(event).unwrap() {
+                    messages.push(XmlItem::as_string(item));
+                }
+            }
+        }
+        assert_eq!(messages,
+                   vec!["<msg id=\"1\">a<b>c</b></msg>".to_string(),
+                        "<msg id=\"2\"/>".to_string()]);
+        assert!(builder.add_event(XmlEvent::EndDocument { position: Default::default() })
+                    .unwrap()
+                    .is_none());
+    }
+
+    #[test]
+    fn errors() {
+        let mut parser = XmlPushParser::new();
+        assert!(parser.feed(b"<a><b").unwrap().len() == 1);
+        match parser.feed(b"></c>") {
+            Err(XmlError::MismatchedCloseTag { .. }) => {}
+            _ => panic!(),
+        }
+
+        let mut parser = XmlPushParser::new();
+        assert!(parser.feed(b"<a>").is_ok());
+        assert!(parser.finish().is_err());
+    }
+}
@@ -0,0 +1,872 @@
+// Licensed to the Square Gears Logic (SGL) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The SGL licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+// http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use std::collections::VecDeque;
+use std::io;
+use std::io::prelude::*;
+use std::mem;
+
+use xml_encoding::{XmlDecoder, XmlEncoding};
+use xml_error::{XmlError, XmlPosition, XmlSpan};
+use xml_escape;
+use xml_item::{XmlDeclaration, XmlDoctype};
+use xml_namespace::XmlQName;
+use xml_parser_options::{ParserOptions, MAX_DEPTH_LIMIT};
+use xml_tokenizer::{XmlToken, XmlTokenizer};
+
+/// How many bytes are read from the source at once.
+const CHUNK_SIZE: usize = 8192;
+
+/// A piece of a document, reported by [XmlReader](struct.XmlReader.html).
+/// Every event carries span of it's markup, and the markup itself as it is
+/// in the document, when options have `preserve_formatting` set.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum XmlEvent {
+    /// `<?xml version="1.0"?>`
+    Declaration {
+        declaration: XmlDeclaration,
+        span: XmlSpan,
+        markup: Option<String>,
+    },
+    /// `<name a="b">`. Empty element `<name/>` is reported as StartElement
+    /// followed by EndElement with the same span.
+    StartElement {
+        name: String,
+        attributes: Vec<(String, String)>,
+        /// Spans of `name="value"` of every attribute, in the same order.
+        attribute_spans: Vec<XmlSpan>,
+        /// Attributes, that refer to entities, which are not predefined, with values
+        /// escaped for writing. Such references are kept as text in `attributes`.
+        escaped_values: Vec<(String, String)>,
+        span: XmlSpan,
+        markup: Option<String>,
+    },
+    EndElement {
+        name: String,
+        span: XmlSpan,
+        markup: Option<String>,
+    },
+    /// Text with references decoded. Text outside of the root element carries no data
+    /// and is reported only when formatting is preserved.
+    Text {
+        text: String,
+        span: XmlSpan,
+        markup: Option<String>,
+    },
+    Comment {
+        text: String,
+        span: XmlSpan,
+        markup: Option<String>,
+    },
+    CData {
+        text: String,
+        span: XmlSpan,
+        markup: Option<String>,
+    },
+    /// `&name;` in text, where name is an entity, that is not predefined.
+    /// Entities are declared by DTD, which is not read, so they are not expanded.
+    EntityReference {
+        name: String,
+        span: XmlSpan,
+        markup: Option<String>,
+    },
+    ProcessingInstruction {
+        target: String,
+        data: String,
+        span: XmlSpan,
+        markup: Option<String>,
+    },
+    Doctype {
+        doctype: XmlDoctype,
+        span: XmlSpan,
+        markup: Option<String>,
+    },
+    /// The last event. Position is the end of the document.
+    EndDocument { position: XmlPosition },
+}
+
+impl XmlEvent {
+    /// Where markup of the event begins.
+    pub fn position(&self) -> XmlPosition {
+        self.span().start
+    }
+
+    /// Where markup of the event begins and ends.
+    pub fn span(&self) -> XmlSpan {
+        match *self {
+            XmlEvent::Declaration { span, .. } |
+            XmlEvent::StartElement { span, .. } |
+            XmlEvent::EndElement { span, .. } |
+            XmlEvent::Text { span, .. } |
+            XmlEvent::Comment { span, .. } |
+            XmlEvent::CData { span, .. } |
+            XmlEvent::EntityReference { span, .. } |
+            XmlEvent::ProcessingInstruction { span, .. } |
+            XmlEvent::Doctype { span, .. } => span,
+            XmlEvent::EndDocument { position } => XmlSpan::new(position, position),
+        }
+    }
+}
+
+/// Element, that is not closed yet.
+struct OpenElement {
+    name: String,
+    position: XmlPosition,
+    /// Namespace prefixes declared by the element. False for `xmlns:prefix=""`,
+    /// that removes the prefix from scope.
+    prefixes: Vec<(String, bool)>,
+}
+
+/// Turns tokens into events, checking that they make a well-formed document:
+/// single root element, matching close tags, bound namespace prefixes, if options
+/// want them, and so on.
+///
+/// Text is fed in pieces, so the same parser serves any source of a document.
+pub struct XmlEventParser {
+    tokenizer: XmlTokenizer,
+    max_depth: usize,
+    text_outside_root: bool,
+    unbound_prefixes: bool,
+    undeclared_entities: bool,
+    recover: bool,
+    preserve_formatting: bool,
+    /// Problems, that are skipped or repaired in recovery mode.
+    diagnostics: Vec<XmlError>,
+    /// How many elements of a skipped subtree are open.
+    skipped_elements: usize,
+    open_elements: Vec<OpenElement>,
+    events: VecDeque<XmlEvent>,
+    is_first_token: bool,
+    has_root: bool,
+    has_doctype: bool,
+    /// General entities declared by the internal subset of DOCTYPE.
+    entities: Vec<String>,
+    /// DOCTYPE refers to an external subset, that may declare any entity.
+    has_external_subset: bool,
+    is_finished: bool,
+}
+
+impl XmlEventParser {
+    pub fn new(options: ParserOptions) -> XmlEventParser {
+        XmlEventParser {
+            max_depth: options.max_depth.min(MAX_DEPTH_LIMIT),
+            text_outside_root: options.text_outside_root,
+            unbound_prefixes: options.unbound_prefixes,
+            undeclared_entities: options.undeclared_entities,
+            recover: options.recover,
+            preserve_formatting: options.preserve_formatting,
+            diagnostics: vec![],
+            skipped_elements: 0,
+            tokenizer: XmlTokenizer::incremental(options),
+            open_elements: vec![],
+            events: VecDeque::new(),
+            is_first_token: true,
+            has_root: false,
+            has_doctype: false,
+            entities: vec![],
+            has_external_subset: false,
+            is_finished: false,
+        }
+    }
+
+    pub fn feed(&mut self, text: &str) {
+        self.tokenizer.feed(text);
+    }
+
+    /// Tells that the whole document is fed.
+    pub fn finish(&mut self) {
+        self.tokenizer.finish();
+    }
+
+    /// Where the parser is now, i.e. right after the last token.
+    pub fn position(&self) -> XmlPosition {
+        self.tokenizer.position()
+    }
+
+    /// Problems found in recovery mode since the last call, in the order of the document.
+    pub fn take_diagnostics(&mut self) -> Vec<XmlError> {
+        mem::take(&mut self.diagnostics)
+    }
+
+    /// Returns next event, or None if more text must be fed first.
+    /// After EndDocument there are no more events.
+    pub fn next_event(&mut self) -> Result<Option<XmlEvent>, XmlError> {
+        while self.events.is_empty() && !self.is_finished {
+            let token = match self.tokenizer.next_token() {
+                Ok(token) => token,
+                Err(error) => {
+                    match error {
+                        XmlError::Io { .. } |
+                        XmlError::Encoding { .. } |
+                        XmlError::LimitExceeded { .. } => return Err(error),
+                        _ => self.report(error)?,
+                    }
+                    self.tokenizer.skip_token();
+                    continue;
+                }
+            };
+            match token {
+                Some(token) => {
+                    self.add_token(token)?;
+                    self.is_first_token = false;
+                }
+                None if self.tokenizer.is_complete() => self.end_document()?,
+                None => return Ok(None),
+            }
+        }
+        Ok(self.events.pop_front())
+    }
+
+    fn add_token(&mut self, token: XmlToken) -> Result<(), XmlError> {
+        let position = self.tokenizer.token_position();
+        let span = XmlSpan::new(position, self.tokenizer.position());
+        let markup = if self.preserve_formatting {
+            Some(self.tokenizer.token_markup())
+        } else {
+            None
+        };
+        if self.skipped_elements > 0 {
+            match token {
+                XmlToken::StartTag { is_empty: false, .. } => self.skipped_elements += 1,
+                XmlToken::EndTag { .. } => self.skipped_elements -= 1,
+                _ => {}
+            }
+            return Ok(());
+        }
+
+        match token {
+            XmlToken::Declaration { attributes } => {
+                if !self.is_first_token {
+                    return self.report(XmlError::Syntax {
+                        message: "XML declaration is allowed only at the beginning of document"
+                                     .to_string(),
+                        position,
+                    });
+                }
+                match Self::parse_declaration(attributes, position) {
+                    Ok(declaration) => {
+                        self.events.push_back(XmlEvent::Declaration {
+                            declaration,
+                            span,
+                            markup,
+                        })
+                    }
+                    Err(error) => self.report(error)?,
+                }
+            }
+            XmlToken::StartTag { name, attributes, is_empty } => {
+                if self.has_root && self.open_elements.is_empty() {
+                    // The whole subtree of the second root is skipped.
+                    self.report(XmlError::Syntax {
+                        message: format!("second root element \"{}\"", name),
+                        position,
+                    })?;
+                    if !is_empty {
+                        self.skipped_elements = 1;
+                    }
+                    return Ok(());
+                }
+                self.has_root = true;
+                if self.open_elements.len() >= self.max_depth {
+                    return Err(XmlError::LimitExceeded {
+                        limit: "max_depth",
+                        value: self.max_depth,
+                        position,
+                    });
+                }
+
+                let element = OpenElement {
+                    name: name.clone(),
+                    position,
+                    prefixes: attributes.iter()
+                                        .filter_map(|(attribute, value)| {
+                                            attribute.strip_prefix("xmlns:").map(|prefix| {
+                                                (prefix.to_string(), !value.is_empty())
+                                            })
+                                        })
+                                        .collect(),
+                };
+                self.open_elements.push(element);
+                if !self.unbound_prefixes {
+                    if let Err(error) = self.check_namespaces(&name, &attributes, position) {
+                        self.report(error)?;
+                    }
+                }
+                let escaped_values = self.tokenizer.escaped_values().to_vec();
+                for (_, escaped) in &escaped_values {
+                    for entity in xml_escape::unresolved_entities(escaped) {
+                        if let Err(error) = self.check_entity(entity, position) {
+                            self.report(error)?;
+                        }
+                    }
+                }
+
+                self.events.push_back(XmlEvent::StartElement {
+                    name: name.clone(),
+                    attributes,
+                    attribute_spans: self.tokenizer.attribute_spans().to_vec(),
+                    escaped_values,
+                    span,
+                    markup: markup.clone(),
+                });
+                if is_empty {
+                    // Start tag is the whole markup of an empty element.
+                    self.open_elements.pop();
+                    self.events.push_back(XmlEvent::EndElement {
+                        name,
+                        span,
+                        markup: markup.map(|_| String::new()),
+                    });
+                }
+            }
+            XmlToken::EndTag { name } => {
+                let expected = match self.open_elements.last() {
+                    Some(element) if element.name == name => None,
+                    Some(element) => Some(element.name.clone()),
+                    None => {
+                        return self.report(XmlError::UnexpectedCloseTag {
+                            found: name,
+                            position,
+                        });
+                    }
+                };
+                if let Some(expected) = expected {
+                    let is_open = self.open_elements.iter().any(|element| element.name == name);
+                    self.report(XmlError::MismatchedCloseTag {
+                        expected,
+                        found: name.clone(),
+                        position,
+                    })?;
+                    if !is_open {
+                        // A stray close tag is dropped.
+                        return Ok(());
+                    }
+                    // Elements, that are not closed, end where their parent does.
+                    while self.open_elements.last().is_some_and(|element| element.name != name) {
+                        self.close_element(XmlSpan::new(position, position));
+                    }
+                }
+                self.open_elements.pop();
+                self.events.push_back(XmlEvent::EndElement { name, span, markup });
+            }
+            XmlToken::Text(text) => {
+                if self.open_elements.is_empty() && !text.trim().is_empty() {
+                    return self.check_outside_root("text", position);
+                }
+                if !self.open_elements.is_empty() || markup.is_some() {
+                    self.events.push_back(XmlEvent::Text { text, span, markup });
+                }
+            }
+            XmlToken::CData(text) => {
+                if self.open_elements.is_empty() {
+                    return self.check_outside_root("CDATA section", position);
+                }
+                self.events.push_back(XmlEvent::CData { text, span, markup });
+            }
+            XmlToken::EntityReference(name) => {
+                if self.open_elements.is_empty() {
+                    return self.check_outside_root("entity reference", position);
+                }
+                if let Err(error) = self.check_entity(&name, position) {
+                    self.report(error)?;
+                }
+                self.events.push_back(XmlEvent::EntityReference { name, span, markup });
+            }
+            XmlToken::Comment(text) => {
+                self.events.push_back(XmlEvent::Comment { text, span, markup });
+            }
+            XmlToken::Doctype(doctype) => {
+                if self.has_root || self.has_doctype {
+                    return self.report(XmlError::Syntax {
+                        message: "DOCTYPE is allowed only once, in front of the root element"
+                                     .to_string(),
+                        position,
+                    });
+                }
+                self.has_doctype = true;
+                self.entities = doctype.entities();
+                self.has_external_subset = doctype.system_id.is_some();
+                self.events.push_back(XmlEvent::Doctype { doctype, span, markup });
+            }
+            XmlToken::ProcessingInstruction { target, data } => {
+                self.events.push_back(XmlEvent::ProcessingInstruction {
+                    target,
+                    data,
+                    span,
+                    markup,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn end_document(&mut self) -> Result<(), XmlError> {
+        let position = self.tokenizer.position();
+        while let Some(error) = self.open_elements.last().map(|element| {
+            XmlError::UnexpectedEof {
+                message: format!("element \"{}\" opened at {} is not closed",
+                                 element.name,
+                                 element.position),
+                position,
+            }
+        }) {
+            self.report(error)?;
+            self.close_element(XmlSpan::new(position, position));
+        }
+        if !self.has_root {
+            self.report(XmlError::UnexpectedEof {
+                message: "document has no root element".to_string(),
+                position,
+            })?;
+        }
+        self.is_finished = true;
+        self.events.push_back(XmlEvent::EndDocument { position });
+        Ok(())
+    }
+
+    /// Closes the innermost open element, that has no close tag.
+    fn close_element(&mut self, span: XmlSpan) {
+        if let Some(element) = self.open_elements.pop() {
+            self.events.push_back(XmlEvent::EndElement {
+                name: element.name,
+                span,
+                markup: None,
+            });
+        }
+    }
+
+    /// Collects the error in recovery mode, or returns it otherwise.
+    fn report(&mut self, error: XmlError) -> Result<(), XmlError> {
+        if !self.recover {
+            return Err(error);
+        }
+        self.diagnostics.push(error);
+        Ok(())
+    }
+
+    /// Element must be already on the stack of open elements,
+    /// so it sees it's own declarations as well as the ones of it's parents.
+    fn check_namespaces(&self,
+                        name: &str,
+                        attributes: &[(String, String)],
+                        position: XmlPosition)
+                        -> Result<(), XmlError> {
+        let names = Some(name).into_iter().chain(attributes.iter().map(|attribute| &*attribute.0));
+        for checked in names {
+            let qname = XmlQName::parse(checked);
+            if qname.local_name.is_empty() || qname.local_name.contains(':') ||
+               qname.prefix.as_ref().is_some_and(|prefix| prefix.is_empty()) {
+                return Err(XmlError::MalformedTag {
+                    message: format!("malformed qualified name \"{}\"", checked),
+                    position,
+                });
+            }
+
+            let prefix = match qname.prefix {
+                Some(ref prefix) if prefix != "xml" && prefix != "xmlns" => prefix,
+                _ => continue,
+            };
+            let is_bound = self.open_elements
+                               .iter()
+                               .rev()
+                               .filter_map(|element| {
+                                   element.prefixes
+                                          .iter()
+                                          .find(|declared| declared.0 == *prefix)
+                                          .map(|declared| declared.1)
+                               })
+                               .next()
+                               .unwrap_or(false);
+            if !is_bound {
+                return Err(XmlError::Syntax {
+                    message: format!("unbound namespace prefix \"{}\" in element \"{}\"",
+                                     prefix,
+                                     name),
+                    position,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Character data outside of the root element is dropped, if options allow it,
+    /// or is an error otherwise.
+    fn check_outside_root(&mut self, what: &str, position: XmlPosition) -> Result<(), XmlError> {
+        if self.text_outside_root {
+            return Ok(());
+        }
+        self.report(XmlError::Syntax {
+            message: format!("{} outside of the root element", what),
+            position,
+        })
+    }
+
+    /// Entities, that are not predefined, must be declared by DOCTYPE, unless options
+    /// allow otherwise or DOCTYPE has an external subset, that may declare them.
+    fn check_entity(&self, name: &str, position: XmlPosition) -> Result<(), XmlError> {
+        if self.undeclared_entities || self.has_external_subset ||
+           self.entities.iter().any(|entity| entity == name) {
+            return Ok(());
+        }
+        Err(XmlError::Syntax {
+            message: format!("undeclared entity \"{}\"", name),
+            position,
+        })
+    }
+
+    fn parse_declaration(attributes: Vec<(String, String)>,
+                         position: XmlPosition)
+                         -> Result<XmlDeclaration, XmlError> {
+        let mut result = XmlDeclaration::new("".to_string());
+        for (name, value) in attributes {
+            match (name.as_str(), value.as_str()) {
+                ("version", _) => result.version = value.clone(),
+                ("encoding", _) => result.encoding = Some(value.clone()),
+                ("standalone", "yes") => result.standalone = Some(true),
+                ("standalone", "no") => result.standalone = Some(false),
+                _ => {
+                    return Err(XmlError::MalformedTag {
+                        message: format!("invalid XML declaration attribute {}=\"{}\"",
+                                         name,
+                                         value),
+                        position,
+                    })
+                }
+            }
+        }
+
+        if result.version.is_empty() {
+            return Err(XmlError::MalformedTag {
+                message: "XML declaration has no version".to_string(),
+                position,
+            });
+        }
+        Ok(result)
+    }
+}
+
+/// Reads a document as a sequence of [events](enum.XmlEvent.html), so documents
+/// of any size can be processed without building the whole DOM:
+///
+/// ```
+/// use libsgl_xml::{XmlEvent, XmlReader};
+///
+/// let mut count = 0;
+/// for event in XmlReader::new("<a><b/><b/></a>".as_bytes()) {
+///     if let XmlEvent::StartElement { ref name, .. } = event.unwrap() {
+///         if name == "b" {
+///             count += 1;
+///         }
+///     }
+/// }
+/// assert_eq!(count, 2);
+/// ```
+///
+/// Encoding of the source is detected the same way
+/// [XmlDom::parse_bytes](struct.XmlDom.html#method.parse_bytes) does it.
+/// The reader stops after EndDocument or the first error.
+pub struct XmlReader<R: Read> {
+    source: R,
+    decoder: XmlDecoder,
+    parser: XmlEventParser,
+    is_source_finished: bool,
+    is_done: bool,
+}
+
+impl<R: Read> XmlReader<R> {
+    pub fn new(source: R) -> XmlReader<R> {
+        Self::with_options(source, &ParserOptions::default())
+    }
+
+    pub fn with_options(source: R, options: &ParserOptions) -> XmlReader<R> {
+        XmlReader {
+            source,
+            decoder: XmlDecoder::new(),
+            parser: XmlEventParser::new(options.clone()),
+            is_source_finished: false,
+            is_done: false,
+        }
+    }
+
+    /// Encoding of the source, once enough of it is read to detect one.
+    pub fn encoding(&self) -> Option<XmlEncoding> {
+        self.decoder.encoding()
+    }
+
+    /// Problems skipped or repaired since the last call, when options have `recover` set.
+    pub fn take_diagnostics(&mut self) -> Vec<XmlError> {
+        self.parser.take_diagnostics()
+    }
+
+    /// Returns next event, reading the source as much as needed for it.
+    pub fn next_event(&mut self) -> Result<XmlEvent, XmlError> {
+        loop {
+            if let Some(event) = self.parser.next_event()? {
+                return Ok(event);
+            }
+            self.read_chunk()?;
+        }
+    }
+
+    fn read_chunk(&mut self) -> Result<(), XmlError> {
+        if self.is_source_finished {
+            // Parser always ends up with EndDocument or an error.
+            return Err(XmlError::UnexpectedEof {
+                message: "document is over".to_string(),
+                position: self.parser.position(),
+            });
+        }
+
+        let mut buffer = [0; CHUNK_SIZE];
+        let len = loop {
+            match self.source.read(&mut buffer) {
+                Ok(len) => break len,
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(error) => {
+                    return Err(XmlError::Io {
+                        error,
+                        position: self.parser.position(),
+                    })
+                }
+            }
+        };
+
+        let is_last = len == 0;
+        let text = self.decoder.decode(&buffer[..len], is_last)?;
+        self.parser.feed(&text);
+        if is_last {
+            self.is_source_finished = true;
+            self.parser.finish();
+        }
+        Ok(())
+    }
+}
+
+impl XmlReader<io::Empty> {
+    /// Reads events from a text, that is decoded already.
+    pub fn from_text(text: &str, options: &ParserOptions) -> XmlReader<io::Empty> {
+        let mut result = Self::with_options(io::empty(), options);
+        result.parser.feed(text);
+        result.parser.finish();
+        result.is_source_finished = true;
+        result
+    }
+}
+
+impl<R: Read> Iterator for XmlReader<R> {
+    type Item = Result<XmlEvent, XmlError>;
+
+    fn next(&mut self) -> Option<Result<XmlEvent, XmlError>> {
+        if self.is_done {
+            return None;
+        }
+        let result = self.next_event();
+        match result {
+            Ok(XmlEvent::EndDocument { .. }) | Err(_) => self.is_done = true,
+            Ok(_) => {}
+        }
+        Some(result)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io;
+    use std::io::prelude::*;
+
+    use super::{XmlEvent, XmlReader};
+    use xml_encoding::XmlEncoding;
+    use xml_error::{XmlError, XmlPosition, XmlSpan};
+    use xml_parser_options::ParserOptions;
+
+    fn events(text: &str) -> Vec<XmlEvent> {
+        XmlReader::from_text(text, &ParserOptions::default())
+            .map(|event| event.unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn element_events() {
+        let span = |start: (usize, usize, usize), end: (usize, usize, usize)| {
+            XmlSpan::new(XmlPosition::new(start.0, start.1, start.2),
+                         XmlPosition::new(end.0, end.1, end.2))
+        };
+        assert_eq!(events("<a x='1'>\n text <b/><!--c--></a>\n<?pi data?>"),
+                   vec![XmlEvent::StartElement {
+                            name: "a".to_string(),
+                            attributes: vec![("x".to_string(), "1".to_string())],
+                            attribute_spans: vec![span((1, 4, 3), (1, 9, 8))],
+                            escaped_values: vec![],
+                            span: span((1, 1, 0), (1, 10, 9)),
+                            markup: None,
+                        },
+                        XmlEvent::Text {
+                            text: "text".to_string(),
+                            span: span((1, 10, 9), (2, 7, 16)),
+                            markup: None,
+                        },
+                        XmlEvent::StartElement {
+                            name: "b".to_string(),
+                            attributes: vec![],
+                            attribute_spans: vec![],
+                            escaped_values: vec![],
+                            span: span((2, 7, 16), (2, 11, 20)),
+                            markup: None,
+                        },
+                        XmlEvent::EndElement {
+                            name: "b".to_string(),
+                            span: span((2, 7, 16), (2, 11, 20)),
+                            markup: None,
+                        },
+                        XmlEvent::Comment {
+                            text: "c".to_string(),
+                            span: span((2, 11, 20), (2, 19, 28)),
+                            markup: None,
+                        },
+                        XmlEvent::EndElement {
+                            name: "a".to_string(),
+                            span: span((2, 19, 28), (2, 23, 32)),
+                            markup: None,
+                        },
+                        XmlEvent::ProcessingInstruction {
+                            target: "pi".to_string(),
+                            data: "data".to_string(),
+                            span: span((3, 1, 33), (3, 12, 44)),
+                            markup: None,
+                        },
+                        XmlEvent::EndDocument { position: XmlPosition::new(3, 12, 44) }]);
+    }
+
+    #[test]
+    fn reading_in_chunks() {
+        let mut text = "<?xml version=\"1.0\"?>\n<list>".to_string();
+        for i in 0..5000 {
+            text.push_str(&format!("<item id=\"{}\">\u{263A} &amp; {}</item>", i, i));
+        }
+        text.push_str("</list>");
+
+        let from_text = events(&text);
+        let from_reader = XmlReader::new(text.as_bytes())
+                              .map(|event| event.unwrap())
+                              .collect::<Vec<_>>();
+        assert_eq!(from_reader.len(), 1 + 2 + 5000 * 3 + 1);
+        assert_eq!(from_reader, from_text);
+
+        let utf16 = XmlEncoding::Utf16Be.encode(&text).unwrap();
+        let mut reader = XmlReader::new(utf16.as_slice());
+        assert_eq!(reader.encoding(), None);
+        assert!(reader.next_event().is_ok());
+        assert_eq!(reader.encoding(), Some(XmlEncoding::Utf16Be));
+        assert_eq!(reader.map(|event| event.unwrap()).collect::<Vec<_>>(),
+                   from_text[1..].to_vec());
+    }
+
+    #[test]
+    fn errors() {
+        let mut reader = XmlReader::new("<a><b></a>".as_bytes());
+        assert!(reader.next().unwrap().is_ok());
+        assert!(reader.next().unwrap().is_ok());
+        match reader.next() {
+            Some(Err(XmlError::MismatchedCloseTag { position, .. })) => {
+                assert_eq!(position, XmlPosition::new(1, 7, 6))
+            }
+            _ => panic!(),
+        }
+        assert!(reader.next().is_none());
+
+        let strict = ParserOptions::strict();
+        assert!(XmlReader::with_options("<a:b/>".as_bytes(), &strict).any(|event| event.is_err()));
+        assert!(XmlReader::with_options("<a xmlns:x='urn:x'><x:b xmlns:x=''/></a>".as_bytes(),
+                                        &strict)
+                    .any(|event| event.is_err()));
+        assert!(XmlReader::with_options("<x:a xmlns:x='urn:x'><x:b/></x:a>".as_bytes(), &strict)
+                    .all(|event| event.is_ok()));
+        assert!(XmlReader::new("<a:b/>".as_bytes()).all(|event| event.is_ok()));
+
+        struct Broken;
+        impl Read for Broken {
+            fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+                Err(io::Error::other("connection reset"))
+            }
+        }
+        let mut reader = XmlReader::new("<a>\n<b/>".as_bytes().chain(Broken));
+        assert_eq!(reader.by_ref().take(3).filter(|event| event.is_ok()).count(), 3);
+        match reader.next() {
+            Some(Err(XmlError::Io { position, .. })) => {
+                assert_eq!(position, XmlPosition::new(2, 5, 8))
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn recovery() {
+        let text = "<a><b><c></b>x</d><e y=1/></a></a><f><g/></f>";
+        let options = ParserOptions::default().set_recover(true);
+        let mut reader = XmlReader::from_text(text, &options);
+        let names = reader.by_ref()
+                          .map(|event| {
+                              match event.unwrap() {
+                                  XmlEvent::StartElement { name, .. } => format!("<{}>", name),
+                                  XmlEvent::EndElement { name, span, .. } => {
+                                      format!("</{}>{}", name, span.start.offset)
+                                  }
+                                  XmlEvent::Text { text, .. } => text,
+                                  _ => "".to_string(),
+                              }
+                          })
+                          .collect::<Vec<_>>()
+                          .concat();
+        assert_eq!(names, "<a><b><c></c>9</b>9x</a>26");
+
+        let diagnostics = reader.take_diagnostics();
+        assert_eq!(diagnostics.len(), 5);
+        match diagnostics[0] {
+            XmlError::MismatchedCloseTag { ref expected, ref found, position } => {
+                assert_eq!((expected.as_str(), found.as_str()), ("c", "b"));
+                assert_eq!(position, XmlPosition::new(1, 10, 9));
+            }
+            _ => panic!(),
+        }
+        match diagnostics[2] {
+            XmlError::MalformedTag { position, .. } => {
+                assert_eq!(position, XmlPosition::new(1, 24, 23))
+            }
+            _ => panic!(),
+        }
+        match diagnostics[3] {
+            XmlError::UnexpectedCloseTag { position, .. } => {
+                assert_eq!(position, XmlPosition::new(1, 31, 30))
+            }
+            _ => panic!(),
+        }
+        match diagnostics[4] {
+            XmlError::Syntax { position, .. } => assert_eq!(position, XmlPosition::new(1, 35, 34)),
+            _ => panic!(),
+        }
+        assert!(reader.take_diagnostics().is_empty());
+
+        // Unclosed elements end with the document.
+        let mut reader = XmlReader::new("<a><b>".as_bytes());
+        assert!(reader.any(|event| event.is_err()));
+        let mut reader = XmlReader::with_options("<a><b>".as_bytes(), &options);
+        assert_eq!(reader.by_ref().filter(|event| event.is_ok()).count(), 5);
+        assert_eq!(reader.take_diagnostics().len(), 2);
+
+        // Limits are not recovered from.
+        let options = options.set_max_depth(1);
+        assert!(XmlReader::from_text("<a><b/></a>", &options).any(|event| event.is_err()));
+    }
+}
@@ -0,0 +1,190 @@
+// Licensed to the Square Gears Logic (SGL) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The SGL licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+// http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::Path;
+
+use xml_dom::XmlSubtreeBuilder;
+use xml_error::XmlError;
+use xml_item::XmlItemRc;
+use xml_parser_options::ParserOptions;
+use xml_reader::{XmlEvent, XmlReader};
+
+/// Callback, that gets elements found by a path.
+struct XmlPathHandler<'a> {
+    path: Vec<String>,
+    callback: Box<dyn FnMut(XmlItemRc) + 'a>,
+}
+
+/// Reads huge documents element by element. Every element, that matches
+/// a registered path, is built and handed to the callback, then dropped,
+/// so memory use does not depend on the size of the document:
+///
+/// ```
+/// use libsgl_xml::{XmlItem, XmlStreamer};
+///
+/// let mut ids = vec![];
+/// XmlStreamer::new()
+///     .on_path("records/record", |record| {
+///         ids.push(XmlItem::get_text(record));
+///     })
+///     .stream("<records><record>1</record><record>2</record></records>".as_bytes())
+///     .unwrap();
+/// assert_eq!(ids, vec!["1".to_string(), "2".to_string()]);
+/// ```
+pub struct XmlStreamer<'a> {
+    handlers: Vec<XmlPathHandler<'a>>,
+    options: ParserOptions,
+}
+
+impl<'a> XmlStreamer<'a> {
+    pub fn new() -> XmlStreamer<'a> {
+        Self::with_options(&ParserOptions::default())
+    }
+
+    pub fn with_options(options: &ParserOptions) -> XmlStreamer<'a> {
+        XmlStreamer {
+            handlers: vec![],
+            options: options.clone(),
+        }
+    }
+
+    /// Registers a callback for elements at a path, such as `records/record`:
+    /// names of elements from the root one down to the element, separated by `/`.
+    /// `*` matches any name.
+    ///
+    /// The element owns it's children, while they only refer to it weakly,
+    /// so it's freed with all of them once the callback drops it.
+    /// Keep the data you need, rather than the element, to keep memory use low.
+    pub fn on_path<F>(&mut self, path: &str, callback: F) -> &mut XmlStreamer<'a>
+        where F: FnMut(XmlItemRc) + 'a
+    {
+        self.handlers.push(XmlPathHandler {
+            path: path.split('/')
+                      .filter(|name| !name.is_empty())
+                      .map(|name| name.to_string())
+                      .collect(),
+            callback: Box::new(callback),
+        });
+        self
+    }
+
+    pub fn stream_file(&mut self, filename: &Path) -> Result<(), XmlError> {
+        self.stream(File::open(filename.as_os_str())?)
+    }
+
+    /// Reads the whole document, calling callbacks on the way.
+    /// Every element may have up to `max_nodes` of the options.
+    pub fn stream<R: Read>(&mut self, source: R) -> Result<(), XmlError> {
+        let mut names: Vec<String> = vec![];
+        // Elements, that are being built, with indexes of their handlers.
+        let mut builders: Vec<(usize, XmlSubtreeBuilder)> = vec![];
+
+        for event in XmlReader::with_options(source, &self.options) {
+            let event = event?;
+            match event {
+                XmlEvent::StartElement { ref name, .. } => {
+                    names.push(name.clone());
+                    for (index, handler) in self.handlers.iter().enumerate() {
+                        if Self::matches(&handler.path, &names) {
+                            builders.push((index, XmlSubtreeBuilder::with_options(0, &self.options)));
+                        }
+                    }
+                }
+                XmlEvent::EndElement { .. } => {
+                    names.pop();
+                }
+                _ => {}
+            }
+
+            let mut i = 0;
+            while i < builders.len() {
+                match builders[i].1.add_event(event.clone())? {
+                    Some(item) => {
+                        let (index, _) = builders.remove(i);
+                        (self.handlers[index].callback)(item);
+                    }
+                    None => i += 1,
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn matches(path: &[String], names: &[String]) -> bool {
+        path.len() == names.len() &&
+        path.iter().zip(names).all(|(expected, name)| expected == "*" || expected == name)
+    }
+}
+
+impl<'a> Default for XmlStreamer<'a> {
+    fn default() -> XmlStreamer<'a> {
+        XmlStreamer::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::rc::Rc;
+
+    use super::XmlStreamer;
+    use xml_item::XmlItem;
+
+    #[test]
+    fn records() {
+        let mut text = "<records>".to_string();
+        for i in 0..1000 {
+            text.push_str(&format!("<record id=\"{}\"><name>n{}</name></record>", i, i));
+        }
+        text.push_str("<summary><record>nested</record></summary></records>");
+
+        let mut records = vec![];
+        let mut names = 0;
+        let mut any = 0;
+        XmlStreamer::new()
+            .on_path("/records/record/", |record| records.push(Rc::downgrade(&record)))
+            .on_path("records/record/name", |_| names += 1)
+            .on_path("records/*", |_| any += 1)
+            .stream(text.as_bytes())
+            .unwrap();
+
+        assert_eq!(records.len(), 1000);
+        assert_eq!(names, 1000);
+        assert_eq!(any, 1001);
+        // Nothing is kept after callbacks.
+        assert!(records.iter().all(|record| record.upgrade().is_none()));
+    }
+
+    #[test]
+    fn errors() {
+        let mut count = 0;
+        let result = XmlStreamer::new()
+                         .on_path("a/b", |_| count += 1)
+                         .stream("<a><b/><b></a>".as_bytes());
+        assert!(result.is_err());
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn subtree_content() {
+        let mut result = String::new();
+        XmlStreamer::new()
+            .on_path("a/b", |b| result = XmlItem::as_string(b))
+            .stream("<a><b x=\"1\">t<!--c--><c/></b></a>".as_bytes())
+            .unwrap();
+        assert_eq!(result, "<b x=\"1\">t<!--c--><c/></b>".to_string());
+    }
+}
@@ -0,0 +1,1341 @@
+// Licensed to the Square Gears Logic (SGL) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The SGL licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+// http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use std::cell::Cell;
+
+use xml_error::{XmlError, XmlPosition, XmlSpan};
+use xml_escape;
+use xml_item::XmlDoctype;
+use xml_parser_options::ParserOptions;
+
+/// A single piece of markup recognized by [XmlTokenizer](struct.XmlTokenizer.html).
+#[derive(Debug, PartialEq)]
+pub enum XmlToken {
+    /// `<name a="b">`, or `<name a="b"/>` when `is_empty` is set.
+    StartTag {
+        name: String,
+        attributes: Vec<(String, String)>,
+        is_empty: bool,
+    },
+    /// `</name>`
+    EndTag { name: String },
+    /// Character data between tags with references decoded.
+    /// Leading and trailing whitespace is trimmed, unless options say otherwise,
+    /// but not next to an EntityReference.
+    Text(String),
+    /// `&name;` in text, where name is an entity, that is not predefined.
+    EntityReference(String),
+    /// `<!-- comment -->` or legacy `<-- comment -->`, without the brackets.
+    Comment(String),
+    /// Raw content of `<![CDATA[ ... ]]>`.
+    CData(String),
+    /// Pseudo-attributes of `<?xml version="1.0" encoding="UTF-8" standalone="yes"?>`.
+    Declaration { attributes: Vec<(String, String)> },
+    /// `<?target data?>`
+    ProcessingInstruction { target: String, data: String },
+    /// `<!DOCTYPE name SYSTEM "system_id" [ internal subset ]>`
+    Doctype(XmlDoctype),
+}
+
+/// Splits XML text into tokens character by character,
+/// so layout of the document does not matter: any number of tags
+/// may share a line and any tag or comment may span several lines.
+///
+/// Text may be fed in pieces. A token, that is not complete yet,
+/// is read again from it's beginning once it's end may be there.
+/// Until then new text is only looked through for the end,
+/// so reading a document in small pieces takes as long as reading it at once.
+pub struct XmlTokenizer {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+    column: usize,
+    offset: usize,
+    token_start: XmlPosition,
+    /// Index of the first character of the last token in chars.
+    token_start_pos: usize,
+    /// The rest of a malformed token is being skipped.
+    is_skipping: bool,
+    /// Spans of attributes of the last start tag.
+    attribute_spans: Vec<XmlSpan>,
+    /// Escaped values of attributes of the last start tag, that refer to unknown entities.
+    escaped_values: Vec<(String, String)>,
+    /// The last token is an EntityReference, so whitespace after it is kept.
+    is_after_reference: bool,
+    options: ParserOptions,
+    /// No more text is going to be fed.
+    is_complete: bool,
+    /// The current token looked past the end of the text fed so far.
+    is_truncated: Cell<bool>,
+    /// The token, that is not complete yet.
+    pending: Option<PendingToken>,
+    /// Characters read, to check that reading in pieces takes linear time.
+    #[cfg(test)]
+    steps: usize,
+}
+
+/// How far a token, that is not complete yet, was looked through for it's end.
+struct PendingToken {
+    /// Index of the first character of the token in chars.
+    start: usize,
+    /// Index of the next character to look at.
+    pos: usize,
+    /// Quote of the attribute value, that is open.
+    quote: Option<char>,
+    is_after_equals: bool,
+    /// DOCTYPE is in it's internal subset, in a declaration of it or in a comment of it.
+    is_in_subset: bool,
+    is_in_declaration: bool,
+    is_in_comment: bool,
+    /// Length, at which the token is read again anyway, so limits are checked.
+    /// It doubles every time.
+    limit_check: usize,
+}
+
+impl XmlTokenizer {
+    #[allow(dead_code)]
+    pub fn new(text: &str) -> XmlTokenizer {
+        Self::with_options(text, ParserOptions::default())
+    }
+
+    /// Tokenizer for the whole text at once.
+    pub fn with_options(text: &str, options: ParserOptions) -> XmlTokenizer {
+        let mut result = Self::incremental(options);
+        result.feed(text);
+        result.finish();
+        result
+    }
+
+    /// Tokenizer, that gets text with [feed](#method.feed).
+    pub fn incremental(options: ParserOptions) -> XmlTokenizer {
+        XmlTokenizer {
+            chars: vec![],
+            pos: 0,
+            line: 1,
+            column: 1,
+            offset: 0,
+            token_start: XmlPosition::default(),
+            token_start_pos: 0,
+            is_skipping: false,
+            attribute_spans: vec![],
+            escaped_values: vec![],
+            is_after_reference: false,
+            options,
+            is_complete: false,
+            is_truncated: Cell::new(false),
+            pending: None,
+            #[cfg(test)]
+            steps: 0,
+        }
+    }
+
+    /// Appends the next piece of text.
+    pub fn feed(&mut self, text: &str) {
+        // Tokens, that are read already, are not needed anymore. They are dropped once
+        // they take most of the buffer, so the rest of it isn't moved on every feed.
+        if self.pos > self.chars.len() / 2 {
+            let drained = self.pos;
+            self.chars.drain(..drained);
+            self.pos = 0;
+            self.token_start_pos = self.token_start_pos.saturating_sub(drained);
+            if let Some(ref mut pending) = self.pending {
+                pending.start -= drained;
+                pending.pos -= drained;
+            }
+        }
+        self.chars.extend(text.chars());
+    }
+
+    /// Tells that the text is over, so unfinished tokens are errors now.
+    pub fn finish(&mut self) {
+        self.is_complete = true;
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.is_complete
+    }
+
+    /// Returns next token or None when the text fed so far is consumed.
+    /// Errors are reported only when they can't be fixed by more text.
+    pub fn next_token(&mut self) -> Result<Option<XmlToken>, XmlError> {
+        if self.is_skipping && !self.skip_malformed() {
+            return Ok(None);
+        }
+        if !self.is_complete && !self.scan_pending() {
+            return Ok(None);
+        }
+
+        self.is_truncated.set(false);
+        let result = self.read_token();
+        if self.is_truncated.get() && !self.is_complete {
+            self.pos = self.token_start_pos;
+            self.line = self.token_start.line;
+            self.column = self.token_start.column;
+            self.offset = self.token_start.offset;
+            if !matches!(self.pending, Some(ref pending) if pending.start == self.pos) {
+                let limit = self.options.max_name_length.min(self.options.max_text_length);
+                self.pending = Some(PendingToken {
+                    start: self.pos,
+                    pos: self.pos,
+                    quote: None,
+                    is_after_equals: false,
+                    is_in_subset: false,
+                    is_in_declaration: false,
+                    is_in_comment: false,
+                    limit_check: limit.saturating_add(1),
+                });
+            }
+            return Ok(None);
+        }
+        self.pending = None;
+        self.is_after_reference = matches!(result, Ok(Some(XmlToken::EntityReference(_))));
+        result
+    }
+
+    /// Skips the token, that next_token() failed to read, up to the next '<',
+    /// so reading may go on after malformed markup.
+    pub fn skip_token(&mut self) {
+        self.pos = self.token_start_pos;
+        self.line = self.token_start.line;
+        self.column = self.token_start.column;
+        self.offset = self.token_start.offset;
+        self.bump();
+        self.is_skipping = true;
+    }
+
+    /// Skips characters up to the next '<'. Returns false, if it's not fed yet.
+    fn skip_malformed(&mut self) -> bool {
+        while let Some(ch) = self.peek() {
+            if ch == '<' {
+                self.is_skipping = false;
+                return true;
+            }
+            self.bump();
+        }
+        false
+    }
+
+    /// Looks through the text, that came since the pending token was read last time.
+    /// Returns true, if the token may be complete now or is long enough to check limits.
+    fn scan_pending(&mut self) -> bool {
+        let mut pending = match self.pending.take() {
+            Some(pending) if pending.start == self.pos => pending,
+            _ => return true,
+        };
+        let result = self.find_token_end(&mut pending);
+        self.pending = Some(pending);
+        result
+    }
+
+    fn find_token_end(&self, pending: &mut PendingToken) -> bool {
+        let start = pending.start;
+        match self.chars.get(start) {
+            None => return false,
+            Some('<') => {}
+            Some(_) => return self.find_text_end(pending),
+        }
+
+        let head = self.chars[start..].iter().take(9).collect::<String>();
+        let openings = ["<?", "<!--", "<--", "<![CDATA[", "<!DOCTYPE"];
+        if openings.iter().any(|opening| opening.len() > head.len() && opening.starts_with(&head)) {
+            // It's not clear yet what the token is, but it's short.
+            return true;
+        }
+        if head.starts_with("<?") {
+            self.find_pattern(pending, start + 2, "?>")
+        } else if head.starts_with("<!--") {
+            self.find_pattern(pending, start + 4, "-->")
+        } else if head.starts_with("<--") {
+            self.find_pattern(pending, start + 3, "-->")
+        } else if head.starts_with("<![CDATA[") {
+            self.find_pattern(pending, start + 9, "]]>")
+        } else if head.starts_with("<!DOCTYPE") {
+            self.find_doctype_end(pending)
+        } else {
+            self.find_tag_end(pending)
+        }
+    }
+
+    /// Text ends with '<' or a reference to an unknown entity.
+    fn find_text_end(&self, pending: &mut PendingToken) -> bool {
+        while pending.pos < self.chars.len() {
+            if Self::is_limit_check(pending) {
+                return true;
+            }
+            if self.chars[pending.pos] == '<' {
+                return true;
+            }
+            if self.chars[pending.pos] == '&' {
+                match self.unresolved_reference_at(pending.pos) {
+                    Ok(Some(_)) => return true,
+                    Ok(None) => {}
+                    // The reference is looked at again, once more text comes.
+                    Err(()) => return false,
+                }
+            }
+            pending.pos += 1;
+        }
+        false
+    }
+
+    fn find_pattern(&self, pending: &mut PendingToken, from: usize, pattern: &str) -> bool {
+        let pattern = pattern.chars().collect::<Vec<_>>();
+        pending.pos = pending.pos.max(from);
+        while pending.pos + pattern.len() <= self.chars.len() {
+            if Self::is_limit_check(pending) {
+                return true;
+            }
+            pending.pos += 1;
+            if self.chars[pending.pos - 1..].starts_with(&pattern) {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Tag ends with '>', that is not in a quoted value.
+    fn find_tag_end(&self, pending: &mut PendingToken) -> bool {
+        while pending.pos < self.chars.len() {
+            if Self::is_limit_check(pending) {
+                return true;
+            }
+            let ch = self.chars[pending.pos];
+            pending.pos += 1;
+            match pending.quote {
+                Some(quote) => {
+                    let is_slashed = self.options.backslash_quotes &&
+                                     self.chars[pending.pos - 2] == '\\';
+                    if ch == quote && !is_slashed {
+                        pending.quote = None;
+                    }
+                }
+                None => {
+                    match ch {
+                        '>' => return true,
+                        '=' => pending.is_after_equals = true,
+                        '"' | '\'' if pending.is_after_equals => {
+                            pending.quote = Some(ch);
+                            pending.is_after_equals = false;
+                        }
+                        _ if ch.is_whitespace() => {}
+                        _ => pending.is_after_equals = false,
+                    }
+                }
+            }
+        }
+        false
+    }
+
+    /// DOCTYPE ends with '>', that is not in the internal subset. Brackets
+    /// and '>' are skipped in quoted literals and comments the same way
+    /// read_internal_subset() skips them.
+    fn find_doctype_end(&self, pending: &mut PendingToken) -> bool {
+        while pending.pos < self.chars.len() {
+            if Self::is_limit_check(pending) {
+                return true;
+            }
+            let pos = pending.pos;
+            let ch = self.chars[pos];
+            if pending.is_in_comment {
+                pending.pos += 1;
+                if ch == '>' && self.chars[pos - 2..pos] == ['-', '-'] {
+                    pending.is_in_comment = false;
+                    pending.is_in_declaration = false;
+                }
+                continue;
+            }
+            if ch == '<' && pending.quote.is_none() && pending.is_in_subset {
+                if pos + 4 > self.chars.len() {
+                    // It's not clear yet, whether a comment starts here.
+                    return false;
+                }
+                if self.chars[pos..pos + 4] == ['<', '!', '-', '-'] {
+                    pending.is_in_comment = true;
+                    pending.pos += 4;
+                    continue;
+                }
+            }
+            pending.pos += 1;
+            match (ch, pending.quote) {
+                (_, Some(opened)) if ch == opened => pending.quote = None,
+                (_, Some(_)) => {}
+                ('"', None) | ('\'', None) if !pending.is_in_subset || pending.is_in_declaration => {
+                    pending.quote = Some(ch)
+                }
+                ('[', None) if !pending.is_in_subset => pending.is_in_subset = true,
+                (']', None) if !pending.is_in_declaration => pending.is_in_subset = false,
+                ('<', None) if pending.is_in_subset => pending.is_in_declaration = true,
+                ('>', None) if pending.is_in_subset => pending.is_in_declaration = false,
+                ('>', None) => return true,
+                _ => {}
+            }
+        }
+        false
+    }
+
+    fn is_limit_check(pending: &mut PendingToken) -> bool {
+        if pending.pos - pending.start < pending.limit_check {
+            return false;
+        }
+        pending.limit_check = pending.limit_check.saturating_mul(2);
+        true
+    }
+
+    fn read_token(&mut self) -> Result<Option<XmlToken>, XmlError> {
+        loop {
+            self.token_start = self.position();
+            self.token_start_pos = self.pos;
+            match self.peek() {
+                None => return Ok(None),
+                Some('<') => {}
+                Some(_) => {
+                    if let Some(name) = self.unresolved_reference() {
+                        for _ in 0..name.chars().count() + 2 {
+                            self.bump();
+                        }
+                        return Ok(Some(XmlToken::EntityReference(name)));
+                    }
+                    let text = self.read_text()?;
+                    if text.is_empty() {
+                        continue;
+                    }
+                    return Ok(Some(XmlToken::Text(text)));
+                }
+            }
+
+            if self.starts_with("<?") {
+                return self.read_processing_instruction().map(Some);
+            }
+            if self.starts_with("<!DOCTYPE") {
+                return self.read_doctype().map(Some);
+            }
+            if self.starts_with("<![CDATA[") {
+                return self.read_cdata().map(Some);
+            }
+            if self.starts_with("<!--") {
+                return self.read_comment("<!--").map(Some);
+            }
+            if self.starts_with("<--") {
+                if !self.options.legacy_comments {
+                    return Err(self.malformed("comment must start with \"<!--\""));
+                }
+                return self.read_comment("<--").map(Some);
+            }
+
+            return self.read_tag().map(Some);
+        }
+    }
+
+    /// Where the last token, returned by next_token(), begins.
+    pub fn token_position(&self) -> XmlPosition {
+        self.token_start
+    }
+
+    /// Markup of the last token, returned by next_token(), as it is in the text.
+    pub fn token_markup(&self) -> String {
+        self.chars[self.token_start_pos..self.pos].iter().collect()
+    }
+
+    /// Spans of `name="value"` of each attribute of the last StartTag token.
+    pub fn attribute_spans(&self) -> &[XmlSpan] {
+        &self.attribute_spans
+    }
+
+    /// Attributes of the last StartTag token, that refer to entities, which are
+    /// not predefined, with their values escaped for writing. Values of the token
+    /// keep such references as text.
+    pub fn escaped_values(&self) -> &[(String, String)] {
+        &self.escaped_values
+    }
+
+    /// Where the tokenizer is now, i.e. right after the last token.
+    pub fn position(&self) -> XmlPosition {
+        XmlPosition::new(self.line, self.column, self.offset)
+    }
+
+    fn read_tag(&mut self) -> Result<XmlToken, XmlError> {
+        self.bump(); // '<'
+        self.skip_whitespace();
+        let is_end_tag = self.eat('/');
+        self.skip_whitespace();
+
+        let name = self.read_name()?;
+        if name.is_empty() {
+            return Err(self.malformed_or_eof("expected tag name", "tag"));
+        }
+
+        if is_end_tag {
+            self.skip_whitespace();
+            if !self.eat('>') {
+                return Err(self.malformed_or_eof("expected '>'", "close tag"));
+            }
+            return Ok(XmlToken::EndTag { name });
+        }
+
+        let mut attributes = vec![];
+        self.attribute_spans.clear();
+        self.escaped_values.clear();
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                None => return Err(self.unexpected_eof("tag")),
+                Some('>') => {
+                    self.bump();
+                    return Ok(XmlToken::StartTag {
+                        name,
+                        attributes,
+                        is_empty: false,
+                    });
+                }
+                Some('/') => {
+                    self.bump();
+                    self.skip_whitespace();
+                    if !self.eat('>') {
+                        return Err(self.malformed_or_eof("expected '>' after '/'", "tag"));
+                    }
+                    return Ok(XmlToken::StartTag {
+                        name,
+                        attributes,
+                        is_empty: true,
+                    });
+                }
+                Some(_) => {
+                    let position = self.position();
+                    let (attribute, escaped) = self.read_attribute("tag")?;
+                    if !self.options.duplicate_attributes &&
+                       attributes.iter().any(|(name, _)| *name == attribute.0) {
+                        return Err(XmlError::Syntax {
+                            message: format!("duplicate attribute \"{}\"", attribute.0),
+                            position,
+                        });
+                    }
+                    if let Some(escaped) = escaped {
+                        self.escaped_values.push((attribute.0.clone(), escaped));
+                    }
+                    attributes.push(attribute);
+                    self.attribute_spans.push(XmlSpan::new(position, self.position()));
+                    if attributes.len() > self.options.max_attributes {
+                        return Err(self.limit_exceeded("max_attributes",
+                                                       self.options.max_attributes));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Reads `<?target data?>`. Target "xml" is reserved for XML declaration.
+    fn read_processing_instruction(&mut self) -> Result<XmlToken, XmlError> {
+        self.bump(); // '<'
+        self.bump(); // '?'
+        let target = self.read_name()?;
+        if target.is_empty() {
+            return Err(self.malformed_or_eof("expected processing instruction target",
+                                             "processing instruction"));
+        }
+        if target == "xml" {
+            return self.read_declaration();
+        }
+
+        self.skip_whitespace();
+        let mut data = String::new();
+        loop {
+            if self.starts_with("?>") {
+                self.bump();
+                self.bump();
+                return Ok(XmlToken::ProcessingInstruction { target, data });
+            }
+            match self.bump() {
+                Some(ch) => self.push_text(&mut data, ch)?,
+                None => return Err(self.unexpected_eof("processing instruction")),
+            }
+        }
+    }
+
+    fn read_declaration(&mut self) -> Result<XmlToken, XmlError> {
+        let mut attributes = vec![];
+        loop {
+            self.skip_whitespace();
+            if self.starts_with("?>") {
+                self.bump();
+                self.bump();
+                return Ok(XmlToken::Declaration { attributes });
+            }
+            if self.peek().is_none() {
+                return Err(self.unexpected_eof("XML declaration"));
+            }
+            attributes.push(self.read_attribute("XML declaration")?.0);
+        }
+    }
+
+    /// Reads `name = "value"`. Value may be quoted with `"` or `'`
+    /// and may contain entity and character references, as well as slashed quotes \\"
+    /// if options allow them. Value is also returned escaped, if it refers to unknown entities.
+    fn read_attribute(&mut self,
+                      context: &str)
+                      -> Result<((String, String), Option<String>), XmlError> {
+        let name = self.read_name()?;
+        if name.is_empty() {
+            return Err(self.malformed_or_eof("expected attribute name", context));
+        }
+        self.skip_whitespace();
+        if !self.eat('=') {
+            return Err(self.malformed_or_eof(&format!("expected '=' after attribute \"{}\"",
+                                                      name),
+                                             context));
+        }
+        self.skip_whitespace();
+
+        let quote = match self.peek() {
+            Some(ch) if ch == '"' || ch == '\'' => ch,
+            None => return Err(self.unexpected_eof(context)),
+            Some(_) => {
+                return Err(self.malformed(&format!("value of attribute \"{}\" must be quoted",
+                                                   name)))
+            }
+        };
+        self.bump();
+
+        let start = self.position();
+        let mut value = String::new();
+        loop {
+            match self.bump() {
+                None => return Err(self.unexpected_eof(context)),
+                Some(ch) if ch == quote && self.options.backslash_quotes &&
+                            value.ends_with('\\') => {
+                    value.pop();
+                    self.push_text(&mut value, ch)?;
+                }
+                Some(ch) if ch == quote => break,
+                Some(ch) => self.push_text(&mut value, ch)?,
+            }
+        }
+
+        self.check_characters(&value, start, true)?;
+        let (value, escaped) = xml_escape::decode_attribute(&value).map_err(|message| {
+            XmlError::Syntax {
+                message,
+                position: start,
+            }
+        })?;
+        Ok(((name, value), escaped))
+    }
+
+    /// Reads text up to the next tag or reference to an unknown entity.
+    fn read_text(&mut self) -> Result<String, XmlError> {
+        let start = self.position();
+        let mut text = String::new();
+        while let Some(ch) = self.peek() {
+            if ch == '<' || ch == '&' && self.unresolved_reference().is_some() {
+                break;
+            }
+            self.push_text(&mut text, ch)?;
+            self.bump();
+        }
+        self.check_characters(&text, start, false)?;
+        if self.options.trim_whitespace && !self.options.preserve_formatting {
+            let mut trimmed = text.as_str();
+            if !self.is_after_reference {
+                trimmed = trimmed.trim_start();
+            }
+            if self.peek() != Some('&') {
+                trimmed = trimmed.trim_end();
+            }
+            return Self::decode(trimmed, start);
+        }
+        Self::decode(&text, start)
+    }
+
+    /// Name of the entity, that `&name;` at the current character refers to,
+    /// unless it's a predefined entity or a character reference.
+    fn unresolved_reference(&self) -> Option<String> {
+        self.unresolved_reference_at(self.pos).unwrap_or_else(|_| {
+            self.is_truncated.set(true);
+            None
+        })
+    }
+
+    /// Same as unresolved_reference() for the character at pos.
+    /// Error means, that the text fed so far ends before the reference does.
+    fn unresolved_reference_at(&self, pos: usize) -> Result<Option<String>, ()> {
+        if self.chars.get(pos) != Some(&'&') {
+            return Ok(None);
+        }
+        let mut reference = String::new();
+        for ch in self.chars[pos + 1..].iter().take(self.options.max_name_length + 1) {
+            reference.push(*ch);
+            if !xml_escape::is_name_char(*ch) {
+                return Ok(xml_escape::unresolved_entity(&reference).map(|name| name.to_string()));
+            }
+        }
+        if pos + 1 + reference.chars().count() >= self.chars.len() {
+            return Err(());
+        }
+        Ok(None)
+    }
+
+    fn decode(text: &str, position: XmlPosition) -> Result<String, XmlError> {
+        xml_escape::decode(text).map_err(|message| {
+            XmlError::Syntax {
+                message,
+                position,
+            }
+        })
+    }
+
+    fn read_comment(&mut self, opening: &str) -> Result<XmlToken, XmlError> {
+        for _ in opening.chars() {
+            self.bump();
+        }
+
+        let mut comment = String::new();
+        loop {
+            if self.starts_with("-->") {
+                if !self.options.loose_characters &&
+                   (comment.contains("--") || comment.ends_with('-')) {
+                    return Err(XmlError::Syntax {
+                        message: "comment can't contain \"--\"".to_string(),
+                        position: self.token_start,
+                    });
+                }
+                for _ in 0..3 {
+                    self.bump();
+                }
+                return Ok(XmlToken::Comment(comment));
+            }
+            match self.bump() {
+                Some(ch) => self.push_text(&mut comment, ch)?,
+                None => return Err(self.unexpected_eof("comment")),
+            }
+        }
+    }
+
+    fn read_cdata(&mut self) -> Result<XmlToken, XmlError> {
+        for _ in "<![CDATA[".chars() {
+            self.bump();
+        }
+
+        let mut data = String::new();
+        loop {
+            if self.starts_with("]]>") {
+                for _ in 0..3 {
+                    self.bump();
+                }
+                return Ok(XmlToken::CData(data));
+            }
+            match self.bump() {
+                Some(ch) => self.push_text(&mut data, ch)?,
+                None => return Err(self.unexpected_eof("CDATA section")),
+            }
+        }
+    }
+
+    fn read_doctype(&mut self) -> Result<XmlToken, XmlError> {
+        for _ in "<!DOCTYPE".chars() {
+            self.bump();
+        }
+        self.skip_whitespace();
+        let mut doctype = XmlDoctype::new(self.read_name()?);
+        if doctype.name.is_empty() {
+            return Err(self.malformed_or_eof("expected root element name", "DOCTYPE"));
+        }
+
+        self.skip_whitespace();
+        if self.starts_with("PUBLIC") || self.starts_with("SYSTEM") {
+            let keyword_position = self.position();
+            let keyword = self.read_name()?;
+            self.skip_whitespace();
+            let literal = self.read_literal()?;
+            if keyword == "PUBLIC" {
+                doctype.public_id = Some(literal);
+                self.skip_whitespace();
+                doctype.system_id = Some(self.read_literal()?);
+            } else if keyword == "SYSTEM" {
+                doctype.system_id = Some(literal);
+            } else {
+                return Err(XmlError::MalformedTag {
+                    message: format!("unknown keyword \"{}\"", keyword),
+                    position: keyword_position,
+                });
+            }
+        }
+
+        self.skip_whitespace();
+        if self.eat('[') {
+            doctype.internal_subset = Some(self.read_internal_subset()?);
+            self.skip_whitespace();
+        }
+
+        if !self.eat('>') {
+            return Err(self.malformed_or_eof("expected '>'", "DOCTYPE"));
+        }
+        Ok(XmlToken::Doctype(doctype))
+    }
+
+    /// Reads everything up to the closing `]`, skipping the ones
+    /// quoted inside of declarations or commented out.
+    fn read_internal_subset(&mut self) -> Result<String, XmlError> {
+        let mut subset = String::new();
+        let mut quote = None;
+        let mut is_in_declaration = false;
+        loop {
+            if quote.is_none() && self.starts_with("<!--") {
+                while !self.starts_with("-->") {
+                    match self.bump() {
+                        Some(ch) => self.push_text(&mut subset, ch)?,
+                        None => return Err(self.unexpected_eof("DOCTYPE")),
+                    }
+                }
+            }
+
+            let ch = match self.bump() {
+                Some(ch) => ch,
+                None => return Err(self.unexpected_eof("DOCTYPE")),
+            };
+            match (ch, quote) {
+                (']', None) if !is_in_declaration => return Ok(subset),
+                ('<', None) => is_in_declaration = true,
+                ('>', None) => is_in_declaration = false,
+                ('"', None) | ('\'', None) if is_in_declaration => quote = Some(ch),
+                (_, Some(opened)) if ch == opened => quote = None,
+                _ => {}
+            }
+            self.push_text(&mut subset, ch)?;
+        }
+    }
+
+    /// Reads a quoted string, that can't contain it's own quote.
+    fn read_literal(&mut self) -> Result<String, XmlError> {
+        let quote = match self.peek() {
+            Some(ch) if ch == '"' || ch == '\'' => ch,
+            None => return Err(self.unexpected_eof("DOCTYPE")),
+            Some(_) => return Err(self.malformed("expected quoted literal")),
+        };
+        self.bump();
+
+        let mut literal = String::new();
+        loop {
+            match self.bump() {
+                Some(ch) if ch == quote => return Ok(literal),
+                Some(ch) => self.push_text(&mut literal, ch)?,
+                None => return Err(self.unexpected_eof("DOCTYPE")),
+            }
+        }
+    }
+
+    fn read_name(&mut self) -> Result<String, XmlError> {
+        let position = self.position();
+        let mut name = String::new();
+        while let Some(ch) = self.peek() {
+            if ch.is_whitespace() || "<>/?=\"'".contains(ch) {
+                break;
+            }
+            name.push(ch);
+            if name.len() > self.options.max_name_length {
+                return Err(self.limit_exceeded("max_name_length", self.options.max_name_length));
+            }
+            self.bump();
+        }
+        if !self.options.loose_characters && !name.is_empty() && !xml_escape::is_name(&name) {
+            return Err(XmlError::Syntax {
+                message: format!("\"{}\" is not a valid name", name),
+                position,
+            });
+        }
+        Ok(name)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(ch) = self.peek() {
+            if !ch.is_whitespace() {
+                break;
+            }
+            self.bump();
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        let result = self.chars.get(self.pos).cloned();
+        if result.is_none() {
+            self.is_truncated.set(true);
+        }
+        result
+    }
+
+    fn starts_with(&self, pattern: &str) -> bool {
+        for (i, ch) in pattern.chars().enumerate() {
+            match self.chars.get(self.pos + i) {
+                Some(&found) if found == ch => {}
+                Some(_) => return false,
+                None => {
+                    self.is_truncated.set(true);
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    fn eat(&mut self, expected: char) -> bool {
+        if self.peek() == Some(expected) {
+            self.bump();
+            return true;
+        }
+        false
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let ch = self.peek();
+        if let Some(ch) = ch {
+            #[cfg(test)]
+            {
+                self.steps += 1;
+            }
+            self.pos += 1;
+            self.offset += ch.len_utf8();
+            if ch == '\n' {
+                self.line += 1;
+                self.column = 1;
+            } else {
+                self.column += 1;
+            }
+        }
+        ch
+    }
+
+    /// Adds a character to text, comment, attribute value and so on,
+    /// as long as it fits into the limit.
+    fn push_text(&self, text: &mut String, ch: char) -> Result<(), XmlError> {
+        if !self.options.loose_characters && !xml_escape::is_xml_char(ch) {
+            return Err(XmlError::Syntax {
+                message: format!("character U+{:04X} is not allowed in XML", ch as u32),
+                position: self.position(),
+            });
+        }
+        text.push(ch);
+        if text.len() > self.options.max_text_length {
+            return Err(self.limit_exceeded("max_text_length", self.options.max_text_length));
+        }
+        Ok(())
+    }
+
+    /// Text or attribute value, as it is in the markup, can have `&` only at
+    /// the start of a reference. Attribute value can't have `<` and text can't have `]]>`.
+    fn check_characters(&self,
+                        raw: &str,
+                        start: XmlPosition,
+                        is_attribute: bool)
+                        -> Result<(), XmlError> {
+        if self.options.loose_characters {
+            return Ok(());
+        }
+        let problem = match xml_escape::bare_ampersand(raw) {
+            Some(pos) => Some((pos, "'&' must start an entity or character reference")),
+            None if is_attribute => raw.find('<').map(|pos| (pos, "attribute value can't contain '<'")),
+            None => raw.find("]]>").map(|pos| (pos, "text can't contain \"]]>\"")),
+        };
+        match problem {
+            Some((pos, message)) => {
+                Err(XmlError::Syntax {
+                    message: message.to_string(),
+                    position: start.advance(&raw[..pos]),
+                })
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn limit_exceeded(&self, limit: &'static str, value: usize) -> XmlError {
+        XmlError::LimitExceeded {
+            limit,
+            value,
+            position: self.token_start,
+        }
+    }
+
+    /// Error at the current character.
+    fn malformed(&self, message: &str) -> XmlError {
+        XmlError::MalformedTag {
+            message: message.to_string(),
+            position: self.position(),
+        }
+    }
+
+    fn unexpected_eof(&self, context: &str) -> XmlError {
+        XmlError::UnexpectedEof {
+            message: format!("{} started at {} is not closed",
+                             context,
+                             self.token_start),
+            position: self.position(),
+        }
+    }
+
+    fn malformed_or_eof(&self, message: &str, context: &str) -> XmlError {
+        if self.peek().is_none() {
+            return self.unexpected_eof(context);
+        }
+        self.malformed(message)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{XmlToken, XmlTokenizer};
+    use xml_error::{XmlError, XmlPosition};
+    use xml_item::XmlDoctype;
+    use xml_parser_options::ParserOptions;
+
+    fn tokens(text: &str) -> Vec<XmlToken> {
+        let mut tokenizer = XmlTokenizer::new(text);
+        let mut result = vec![];
+        while let Some(token) = tokenizer.next_token().unwrap() {
+            result.push(token);
+        }
+        result
+    }
+
+    #[test]
+    fn tags_on_one_line() {
+        assert_eq!(tokens("<a><b/><c x='1' y=\"2\"/></a>"),
+                   vec![XmlToken::StartTag {
+                            name: "a".to_string(),
+                            attributes: vec![],
+                            is_empty: false,
+                        },
+                        XmlToken::StartTag {
+                            name: "b".to_string(),
+                            attributes: vec![],
+                            is_empty: true,
+                        },
+                        XmlToken::StartTag {
+                            name: "c".to_string(),
+                            attributes: vec![("x".to_string(), "1".to_string()),
+                                             ("y".to_string(), "2".to_string())],
+                            is_empty: true,
+                        },
+                        XmlToken::EndTag { name: "a".to_string() }]);
+    }
+
+    #[test]
+    fn quotes_and_brackets_in_values() {
+        assert_eq!(tokens("<a x=\"1 > 0\" y=\"_\\\"_\" z='\"'/>"),
+                   vec![XmlToken::StartTag {
+                            name: "a".to_string(),
+                            attributes: vec![("x".to_string(), "1 > 0".to_string()),
+                                             ("y".to_string(), "_\"_".to_string()),
+                                             ("z".to_string(), "\"".to_string())],
+                            is_empty: true,
+                        }]);
+        // Values are not normalized, line breaks and tabs are kept.
+        assert_eq!(tokens("<a x='1\n\t2'/>"),
+                   vec![XmlToken::StartTag {
+                            name: "a".to_string(),
+                            attributes: vec![("x".to_string(), "1\n\t2".to_string())],
+                            is_empty: true,
+                        }]);
+    }
+
+    #[test]
+    fn multi_line_tags_and_comments() {
+        assert_eq!(tokens("<-- <fake/>\n -->\n<!--\n<fake/> -->\n<\n a\n x\n =\n 'b'\n\n/\n>"),
+                   vec![XmlToken::Comment(" <fake/>\n ".to_string()),
+                        XmlToken::Comment("\n<fake/> ".to_string()),
+                        XmlToken::StartTag {
+                            name: "a".to_string(),
+                            attributes: vec![("x".to_string(), "b".to_string())],
+                            is_empty: true,
+                        }]);
+    }
+
+    #[test]
+    fn text() {
+        assert_eq!(tokens("<a> Hello,\n world </a>\n"),
+                   vec![XmlToken::StartTag {
+                            name: "a".to_string(),
+                            attributes: vec![],
+                            is_empty: false,
+                        },
+                        XmlToken::Text("Hello,\n world".to_string()),
+                        XmlToken::EndTag { name: "a".to_string() }]);
+    }
+
+    #[test]
+    fn references() {
+        assert_eq!(tokens("<a b='&lt;&#x263A;&apos;'>&amp;&#169; &unknown; </a>"),
+                   vec![XmlToken::StartTag {
+                            name: "a".to_string(),
+                            attributes: vec![("b".to_string(), "<\u{263A}'".to_string())],
+                            is_empty: false,
+                        },
+                        XmlToken::Text("&\u{A9} ".to_string()),
+                        XmlToken::EntityReference("unknown".to_string()),
+                        XmlToken::EndTag { name: "a".to_string() }]);
+
+        // Unknown entities in attribute values are kept escaped.
+        let mut tokenizer = XmlTokenizer::new("<a b='&x; &amp; \"' c='d'/>");
+        tokenizer.next_token().unwrap();
+        assert_eq!(tokenizer.escaped_values(),
+                   &[("b".to_string(), "&x; &amp; &quot;".to_string())]);
+        assert_eq!(tokens("<![CDATA[&amp;]]>"),
+                   vec![XmlToken::CData("&amp;".to_string())]);
+        assert!(XmlTokenizer::new("<a b='&#1;'/>").next_token().is_err());
+    }
+
+    #[test]
+    fn cdata() {
+        assert_eq!(tokens("<![CDATA[ <a> & ]] ]>\n]]><![CDATA[]]>"),
+                   vec![XmlToken::CData(" <a> & ]] ]>\n".to_string()),
+                        XmlToken::CData("".to_string())]);
+    }
+
+    #[test]
+    fn declaration() {
+        assert_eq!(tokens("<?xml version=\"1.0\" encoding='UTF-8'?><a/>")[0],
+                   XmlToken::Declaration {
+                       attributes: vec![("version".to_string(), "1.0".to_string()),
+                                        ("encoding".to_string(), "UTF-8".to_string())],
+                   });
+    }
+
+    #[test]
+    fn processing_instructions() {
+        assert_eq!(tokens("<?xml-stylesheet href=\"a.xsl\"?><?tool  a ? b ?><?empty?>"),
+                   vec![XmlToken::ProcessingInstruction {
+                            target: "xml-stylesheet".to_string(),
+                            data: "href=\"a.xsl\"".to_string(),
+                        },
+                        XmlToken::ProcessingInstruction {
+                            target: "tool".to_string(),
+                            data: "a ? b ".to_string(),
+                        },
+                        XmlToken::ProcessingInstruction {
+                            target: "empty".to_string(),
+                            data: "".to_string(),
+                        }]);
+    }
+
+    #[test]
+    fn doctype() {
+        let text = "<!DOCTYPE root PUBLIC '-//A//B' \"x.dtd\" [\n\
+                    <!ENTITY a \"]>\">\n<!-- ] -->\n]\n><root/>";
+        let mut doctype = XmlDoctype::new("root".to_string());
+        doctype.public_id = Some("-//A//B".to_string());
+        doctype.system_id = Some("x.dtd".to_string());
+        doctype.internal_subset = Some("\n<!ENTITY a \"]>\">\n<!-- ] -->\n".to_string());
+        assert_eq!(tokens(text)[0], XmlToken::Doctype(doctype));
+
+        assert_eq!(tokens("<!DOCTYPE html>")[0],
+                   XmlToken::Doctype(XmlDoctype::new("html".to_string())));
+
+        // Public identifier comes with a system one.
+        assert!(XmlTokenizer::new("<!DOCTYPE a PUBLIC \"x\">").next_token().is_err());
+    }
+
+    #[test]
+    fn positions() {
+        let mut tokenizer = XmlTokenizer::new("<a>\n  <\u{444}\u{44B}/>");
+        tokenizer.next_token().unwrap();
+        assert_eq!(tokenizer.token_position(), XmlPosition::new(1, 1, 0));
+        tokenizer.next_token().unwrap();
+        assert_eq!(tokenizer.token_position(), XmlPosition::new(2, 3, 6));
+        assert_eq!(tokenizer.position(), XmlPosition::new(2, 8, 13));
+
+        let mut tokenizer = XmlTokenizer::new("<a>\n<b x='1' y></b>");
+        tokenizer.next_token().unwrap();
+        match tokenizer.next_token() {
+            Err(XmlError::MalformedTag { position, .. }) => {
+                assert_eq!(position, XmlPosition::new(2, 11, 14))
+            }
+            _ => panic!(),
+        }
+        match XmlTokenizer::new("<a x='1").next_token() {
+            Err(XmlError::UnexpectedEof { position, .. }) => {
+                assert_eq!(position, XmlPosition::new(1, 8, 7))
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn errors() {
+        assert!(XmlTokenizer::new("<a x=1/>").next_token().is_err());
+        assert!(XmlTokenizer::new("<a x='1'").next_token().is_err());
+        assert!(XmlTokenizer::new("<-- unterminated").next_token().is_err());
+        assert!(XmlTokenizer::new("</>").next_token().is_err());
+        assert!(XmlTokenizer::new("<![CDATA[ ]>").next_token().is_err());
+        assert!(XmlTokenizer::new("<?xml version='1.0'").next_token().is_err());
+        assert!(XmlTokenizer::new("<? nameless ?>").next_token().is_err());
+        assert!(XmlTokenizer::new("<?tool data").next_token().is_err());
+        assert!(XmlTokenizer::new("<!DOCTYPE a SYSTEM>").next_token().is_err());
+        assert!(XmlTokenizer::new("<!DOCTYPE a [ <!ENTITY b 'c'> >").next_token().is_err());
+    }
+
+    fn strict_tokens(text: &str) -> Result<Vec<XmlToken>, XmlError> {
+        let mut tokenizer = XmlTokenizer::with_options(text, ParserOptions::strict());
+        let mut result = vec![];
+        while let Some(token) = tokenizer.next_token()? {
+            result.push(token);
+        }
+        Ok(result)
+    }
+
+    #[test]
+    fn incremental() {
+        let text = "<?xml version='1.0'?><a x='1'>\n text &amp; more <!-- c --><b/></a>";
+        let whole = tokens(text);
+        for size in 1..text.len() {
+            let mut tokenizer = XmlTokenizer::incremental(ParserOptions::default());
+            let mut result = vec![];
+            for chunk in text.as_bytes().chunks(size) {
+                tokenizer.feed(::std::str::from_utf8(chunk).unwrap());
+                while let Some(token) = tokenizer.next_token().unwrap() {
+                    result.push(token);
+                }
+            }
+            tokenizer.finish();
+            while let Some(token) = tokenizer.next_token().unwrap() {
+                result.push(token);
+            }
+            assert_eq!(result, whole);
+            assert_eq!(tokenizer.position(), XmlPosition::new(2, 36, 66));
+        }
+
+        let mut tokenizer = XmlTokenizer::incremental(ParserOptions::default());
+        tokenizer.feed("<a x='1");
+        assert!(tokenizer.next_token().unwrap().is_none());
+        tokenizer.finish();
+        assert!(tokenizer.next_token().is_err());
+
+        // Endless text is an error as soon as it exceeds the limit.
+        let options = ParserOptions::default().set_max_text_length(100);
+        let mut tokenizer = XmlTokenizer::incremental(options);
+        tokenizer.feed("<a>");
+        assert!(tokenizer.next_token().unwrap().is_some());
+        let mut result = Ok(None);
+        for _ in 0..100 {
+            tokenizer.feed("0123456789");
+            result = tokenizer.next_token();
+            if result.is_err() {
+                break;
+            }
+        }
+        match result {
+            Err(XmlError::LimitExceeded { limit, position, .. }) => {
+                assert_eq!(limit, "max_text_length");
+                assert_eq!(position, XmlPosition::new(1, 4, 3));
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn incremental_takes_linear_time() {
+        let long = "x".repeat(100_000);
+        let texts = [format!("<a>{}</a>", long),
+                     format!("<!--{}-->", long),
+                     format!("<a x='{}' y=\"a > b\"/>", long),
+                     format!("<a>{}</a>", "&amp;".repeat(20_000)),
+                     format!("<!DOCTYPE a [{}]>", "<!ENTITY x \"]>\">".repeat(5_000))];
+        for text in &texts {
+            let mut tokenizer = XmlTokenizer::incremental(ParserOptions::default());
+            let mut count = 0;
+            for chunk in text.as_bytes().chunks(7) {
+                tokenizer.feed(::std::str::from_utf8(chunk).unwrap());
+                while tokenizer.next_token().unwrap().is_some() {
+                    count += 1;
+                }
+            }
+            tokenizer.finish();
+            while tokenizer.next_token().unwrap().is_some() {
+                count += 1;
+            }
+            assert_eq!(count, tokens(text).len());
+            assert!(tokenizer.steps < 4 * text.len());
+        }
+    }
+
+    #[test]
+    fn options() {
+        assert_eq!(strict_tokens("<a x='\\'/>").unwrap(),
+                   vec![XmlToken::StartTag {
+                            name: "a".to_string(),
+                            attributes: vec![("x".to_string(), "\\".to_string())],
+                            is_empty: true,
+                        }]);
+        assert_eq!(strict_tokens("<a> b </a>").unwrap()[1],
+                   XmlToken::Text(" b ".to_string()));
+        match strict_tokens("<a x='1' y='2' x='3'/>") {
+            Err(XmlError::Syntax { position, .. }) => {
+                assert_eq!(position, XmlPosition::new(1, 16, 15))
+            }
+            _ => panic!(),
+        }
+        assert!(strict_tokens("<-- legacy -->").is_err());
+
+        assert_eq!(tokens("<a x='\\''/>"), strict_tokens("<a x=\"'\"/>").unwrap());
+        assert_eq!(tokens("<a x='1' x='2'/>").len(), 1);
+    }
+
+    #[test]
+    fn well_formed_characters() {
+        let cases = [("<a><!-- a -- b --></a>", XmlPosition::new(1, 4, 3)),
+                     ("<a><!-- a ---></a>", XmlPosition::new(1, 4, 3)),
+                     ("<1a/>", XmlPosition::new(1, 2, 1)),
+                     ("<a -b='2'/>", XmlPosition::new(1, 4, 3)),
+                     ("<a>a & b</a>", XmlPosition::new(1, 6, 5)),
+                     ("<a x='&#x;'/>", XmlPosition::new(1, 7, 6)),
+                     ("<a x='<'/>", XmlPosition::new(1, 7, 6)),
+                     ("<a>]]></a>", XmlPosition::new(1, 4, 3)),
+                     ("<a>\u{1}</a>", XmlPosition::new(1, 4, 3))];
+        for &(text, expected) in &cases {
+            match strict_tokens(text) {
+                Err(XmlError::Syntax { position, .. }) => assert_eq!(position, expected),
+                result => panic!("{:?} for {}", result, text),
+            }
+            assert!(!tokens(text).is_empty());
+        }
+
+        assert_eq!(strict_tokens("<a x='&lt;&#60;&#x3C;'>a &amp; ]]&gt; &b; <!-- - --></a>")
+                       .unwrap()
+                       .len(),
+                   6);
+    }
+
+    #[test]
+    fn skip_token() {
+        let mut tokenizer = XmlTokenizer::new("<a><b x=1>text</b><c/></a>");
+        assert!(tokenizer.next_token().unwrap().is_some());
+        assert!(tokenizer.next_token().is_err());
+        tokenizer.skip_token();
+        assert_eq!(tokenizer.next_token().unwrap(),
+                   Some(XmlToken::EndTag { name: "b".to_string() }));
+        assert_eq!(tokenizer.token_position(), XmlPosition::new(1, 15, 14));
+
+        // Skipping goes on, when more text comes.
+        let mut tokenizer = XmlTokenizer::incremental(ParserOptions::strict());
+        tokenizer.feed("<-- x");
+        assert!(tokenizer.next_token().is_err());
+        tokenizer.skip_token();
+        assert!(tokenizer.next_token().unwrap().is_none());
+        tokenizer.feed(" --><a/>");
+        tokenizer.finish();
+        assert_eq!(tokenizer.next_token().unwrap(),
+                   Some(XmlToken::StartTag {
+                       name: "a".to_string(),
+                       attributes: vec![],
+                       is_empty: true,
+                   }));
+        assert!(tokenizer.next_token().unwrap().is_none());
+    }
+}
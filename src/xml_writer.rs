@@ -0,0 +1,377 @@
+// Licensed to the Square Gears Logic (SGL) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The SGL licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+// http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Printing of XmlItem trees and XmlDocument nodes, so both look the same.
+
+use xml_attributes::XmlAttributes;
+use xml_encoding::XmlEncoding;
+use xml_escape;
+use xml_item::{XmlDeclaration, XmlDoctype, XmlItem, XmlItemKind, XmlItemRc};
+use xml_writer_options::WriterOptions;
+
+/// Node, that can be printed as XML.
+pub trait XmlWriterNode: Sized {
+    fn kind(&self) -> XmlItemKind;
+    fn name(&self) -> &str;
+    fn value(&self) -> &str;
+    fn attributes(&self) -> &XmlAttributes;
+    fn public_id(&self) -> Option<&String>;
+    fn system_id(&self) -> Option<&String>;
+    fn markup(&self) -> Option<&String>;
+    fn end_markup(&self) -> Option<&String>;
+    fn child_count(&self) -> usize;
+    /// Calls f for every child node in their order.
+    fn for_each_child(&self, f: &mut dyn FnMut(&Self));
+}
+
+/// Prints a document part by part. Parts with original markup come with
+/// whitespace around them, the rest are put on new lines.
+pub struct XmlDocumentWriter<'a> {
+    result: String,
+    format: XmlFormat<'a>,
+}
+
+impl<'a> XmlDocumentWriter<'a> {
+    /// Writer of a document, that is going to be stored in the encoding.
+    /// Characters, that the encoding can't represent, are written as character
+    /// references in text and attribute values.
+    pub fn new(options: &'a WriterOptions, encoding: XmlEncoding) -> XmlDocumentWriter<'a> {
+        XmlDocumentWriter {
+            result: String::new(),
+            format: XmlFormat { options, encoding },
+        }
+    }
+
+    pub fn declaration(&mut self, declaration: Option<&XmlDeclaration>, markup: Option<&String>) {
+        if let Some(declaration) = declaration {
+            match markup {
+                Some(markup) if self.format.can_encode(markup) => self.result.push_str(markup),
+                _ => {
+                    self.start_part(false);
+                    self.result.push_str(&declaration.as_string());
+                }
+            }
+        }
+    }
+
+    /// Root element or a node of prolog or epilog.
+    pub fn node<N: XmlWriterNode>(&mut self, node: &N) {
+        self.start_part(is_preserved(node));
+        self.format.write_node(&mut self.result, node, Some(""), "\t");
+    }
+
+    pub fn finish(self) -> String {
+        self.result
+    }
+
+    /// Part without original markup goes on a new line.
+    fn start_part(&mut self, is_preserved: bool) {
+        if !is_preserved && !self.result.is_empty() && !self.result.ends_with('\n') {
+            self.result.push('\n');
+        }
+    }
+}
+
+/// Prints the item with it's prolog and epilog. The declaration, if given,
+/// is printed instead of the item's own one.
+pub fn write_item(rc: XmlItemRc,
+                  declaration: Option<XmlDeclaration>,
+                  mut writer: XmlDocumentWriter)
+                  -> String {
+    match *rc.borrow() {
+        Some(ref xml_item) => {
+            match declaration {
+                Some(ref declaration) => writer.declaration(Some(declaration), None),
+                None => {
+                    writer.declaration(xml_item.declaration.as_ref(),
+                                       xml_item.declaration_markup.as_ref())
+                }
+            }
+            for node in &xml_item.prolog {
+                if let Some(ref node) = *node.borrow() {
+                    writer.node(node);
+                }
+            }
+            writer.node::<XmlItem>(xml_item);
+            for node in &xml_item.epilog {
+                if let Some(ref node) = *node.borrow() {
+                    writer.node(node);
+                }
+            }
+            writer.finish()
+        }
+        None => "".to_string(),
+    }
+}
+
+/// Document type declaration, that a Doctype node stands for.
+pub fn doctype<N: XmlWriterNode>(node: &N) -> XmlDoctype {
+    XmlDoctype {
+        name: node.name().to_string(),
+        public_id: node.public_id().cloned(),
+        system_id: node.system_id().cloned(),
+        internal_subset: if node.value().is_empty() {
+            None
+        } else {
+            Some(node.value().to_string())
+        },
+    }
+}
+
+/// How nodes are printed.
+struct XmlFormat<'a> {
+    options: &'a WriterOptions,
+    encoding: XmlEncoding,
+}
+
+impl<'a> XmlFormat<'a> {
+    /// Prints the node after indent. Child nodes get one more unit of indentation.
+    /// Node without indent is a part of mixed content and is printed in one line.
+    fn write_node<N: XmlWriterNode>(&self,
+                                    result: &mut String,
+                                    node: &N,
+                                    indent: Option<&str>,
+                                    unit: &str) {
+        if let Some(markup) = node.markup() {
+            // Markup with characters, that the encoding can't represent, is printed anew.
+            if node.kind() != XmlItemKind::Element && self.can_encode(markup) {
+                result.push_str(markup);
+                return;
+            }
+        }
+        if let Some(end_markup) = node.end_markup() {
+            // Element, that had no nodes, needs both tags printed anew to get some.
+            if !end_markup.is_empty() || node.child_count() == 0 {
+                self.write_preserved(result, node, end_markup, unit);
+                return;
+            }
+        }
+
+        let is_inline = indent.is_none();
+        let indent = indent.unwrap_or("");
+        result.push_str(indent);
+        match node.kind() {
+            XmlItemKind::Text => {
+                result.push_str(&self.references(&xml_escape::escape_text(node.value())))
+            }
+            XmlItemKind::Comment => {
+                result.push_str("<!--");
+                result.push_str(node.value());
+                result.push_str("-->");
+            }
+            XmlItemKind::CData => self.write_cdata(result, node.value()),
+            XmlItemKind::ProcessingInstruction => {
+                result.push_str("<?");
+                result.push_str(node.name());
+                if !node.value().is_empty() {
+                    result.push(' ');
+                    result.push_str(node.value());
+                }
+                result.push_str("?>");
+            }
+            XmlItemKind::Doctype => result.push_str(&doctype(node).as_string()),
+            XmlItemKind::EntityReference => {
+                result.push('&');
+                result.push_str(node.name());
+                result.push(';');
+            }
+            XmlItemKind::Element => self.write_element(result, node, is_inline, indent, unit),
+        }
+    }
+
+    fn write_element<N: XmlWriterNode>(&self,
+                                       result: &mut String,
+                                       node: &N,
+                                       is_inline: bool,
+                                       indent: &str,
+                                       unit: &str) {
+        self.write_start_tag(result, node);
+        if node.child_count() == 0 {
+            result.push_str("/>");
+            return;
+        }
+        result.push('>');
+
+        // Whitespace added between nodes of mixed content would change the text,
+        // so such element and everything in it stays on a single line.
+        let mut is_mixed = is_inline;
+        node.for_each_child(&mut |child| is_mixed |= is_character_data(child));
+        if is_mixed {
+            node.for_each_child(&mut |child| self.write_node(result, child, None, unit));
+        } else {
+            let node_indent = format!("{}{}", indent, unit);
+            node.for_each_child(&mut |child| {
+                result.push('\n');
+                self.write_node(result, child, Some(&node_indent), unit);
+            });
+            result.push('\n');
+            result.push_str(indent);
+        }
+        result.push_str("</");
+        result.push_str(node.name());
+        result.push('>');
+    }
+
+    /// `<name a="b"` without the closing bracket.
+    fn write_start_tag<N: XmlWriterNode>(&self, result: &mut String, node: &N) {
+        result.push('<');
+        result.push_str(node.name());
+        let attributes = if self.options.sort_attributes {
+            node.attributes().sorted()
+        } else {
+            node.attributes().iter().collect()
+        };
+        for (key, value) in attributes {
+            let value = match node.attributes().get_escaped(key) {
+                Some(escaped) => escaped.clone(),
+                None => xml_escape::escape_attribute(value),
+            };
+            result.push(' ');
+            result.push_str(key);
+            result.push_str("=\"");
+            result.push_str(&self.references(&value));
+            result.push('"');
+        }
+    }
+
+    /// Prints element with it's original tags, or the changed start tag,
+    /// and it's nodes as they are, since whitespace between them is kept in Text nodes.
+    /// Nodes, that were added since, go on their own lines indented like the ones
+    /// read with them, if these are on their own lines too.
+    fn write_preserved<N: XmlWriterNode>(&self,
+                                         result: &mut String,
+                                         node: &N,
+                                         end_markup: &str,
+                                         unit: &str) {
+        let start = result.len();
+        match node.markup() {
+            Some(markup) if self.can_encode(markup) => result.push_str(markup),
+            _ => {
+                self.write_start_tag(result, node);
+                result.push_str(if end_markup.is_empty() { "/>" } else { ">" });
+            }
+        }
+        let (indent, unit) = node_indentation(node, unit);
+        node.for_each_child(&mut |child| {
+            let is_anew = is_written_anew(child) && !is_character_data(child);
+            let line_start = if is_anew {
+                result[start..].rfind('\n').map(|pos| start + pos + 1)
+            } else {
+                None
+            };
+            match line_start {
+                Some(line_start) if result[line_start..].trim().is_empty() => {
+                    let rest = result.split_off(line_start);
+                    if is_preserved(child) {
+                        // Changed node keeps it's place and indentation.
+                        self.write_node(result, child, Some(&rest), &unit);
+                    } else {
+                        // The line is moved after the new node, so tags after it
+                        // keep their place.
+                        self.write_node(result, child, Some(&indent), &unit);
+                        result.push('\n');
+                        result.push_str(&rest);
+                    }
+                }
+                _ => self.write_node(result, child, None, &unit),
+            }
+        });
+        result.push_str(end_markup);
+    }
+
+    /// CDATA section can't contain "]]>", so it is split between two sections.
+    /// Characters, that the encoding can't represent, go between sections
+    /// as character references.
+    fn write_cdata(&self, result: &mut String, data: &str) {
+        result.push_str("<![CDATA[");
+        for ch in data.replace("]]>", "]]]]><![CDATA[>").chars() {
+            if self.encoding.can_encode(ch) {
+                result.push(ch);
+            } else {
+                result.push_str(&format!("]]>&#x{:X};<![CDATA[", ch as u32));
+            }
+        }
+        result.push_str("]]>");
+    }
+
+    /// Replaces characters of escaped text, that the encoding can't represent,
+    /// with character references.
+    fn references(&self, escaped: &str) -> String {
+        if self.can_encode(escaped) {
+            return escaped.to_string();
+        }
+        let mut result = String::with_capacity(escaped.len());
+        for ch in escaped.chars() {
+            if self.encoding.can_encode(ch) {
+                result.push(ch);
+            } else {
+                result.push_str(&format!("&#x{:X};", ch as u32));
+            }
+        }
+        result
+    }
+
+    fn can_encode(&self, text: &str) -> bool {
+        text.chars().all(|ch| self.encoding.can_encode(ch))
+    }
+}
+
+/// Indentation of nodes on their own lines and the step of it,
+/// taken from whitespace before the first such node and before the end tag.
+fn node_indentation<N: XmlWriterNode>(node: &N, unit: &str) -> (String, String) {
+    let mut children = vec![];
+    node.for_each_child(&mut |child| children.push((is_character_data(child), line_indent(child))));
+    let end_indent = children.last().and_then(|child| child.1.clone()).unwrap_or_default();
+    let node_indent = children.windows(2)
+                              .filter(|pair| !pair[1].0)
+                              .filter_map(|pair| pair[0].1.clone())
+                              .next();
+    match node_indent {
+        Some(indent) => {
+            let unit = match indent.strip_prefix(end_indent.as_str()) {
+                Some(step) if !step.is_empty() => step.to_string(),
+                _ => unit.to_string(),
+            };
+            (indent, unit)
+        }
+        None => (format!("{}{}", end_indent, unit), unit.to_string()),
+    }
+}
+
+/// Whitespace, that the next line starts with, if the node is whitespace with a line break.
+fn line_indent<N: XmlWriterNode>(node: &N) -> Option<String> {
+    let value = node.value();
+    if node.kind() != XmlItemKind::Text || !value.trim().is_empty() {
+        return None;
+    }
+    value.rfind('\n').map(|pos| value[pos + 1..].to_string())
+}
+
+fn is_character_data<N: XmlWriterNode>(node: &N) -> bool {
+    matches!(node.kind(),
+             XmlItemKind::Text | XmlItemKind::CData | XmlItemKind::EntityReference)
+}
+
+fn is_preserved<N: XmlWriterNode>(node: &N) -> bool {
+    node.markup().is_some() || node.end_markup().is_some()
+}
+
+/// Node is printed from it's fields rather than it's original markup.
+fn is_written_anew<N: XmlWriterNode>(node: &N) -> bool {
+    match node.end_markup() {
+        Some(end_markup) if !end_markup.is_empty() || node.child_count() == 0 => false,
+        _ => node.markup().is_none() || node.kind() == XmlItemKind::Element,
+    }
+}
@@ -0,0 +1,41 @@
+// Licensed to the Square Gears Logic (SGL) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The SGL licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+// http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+/// How [XmlItem::as_string_with](struct.XmlItem.html#method.as_string_with) and
+/// [XmlDom::save_file_with](struct.XmlDom.html#method.save_file_with) print a document.
+///
+/// ```
+/// use libsgl_xml::{WriterOptions, XmlItem};
+///
+/// let item = XmlItem::new("a".to_string());
+/// XmlItem::set_attribute(item.clone(), "z".to_string(), "1".to_string());
+/// XmlItem::set_attribute(item.clone(), "b".to_string(), "2".to_string());
+/// assert_eq!(XmlItem::as_string(item.clone()), "<a z=\"1\" b=\"2\"/>");
+/// assert_eq!(XmlItem::as_string_with(item, &WriterOptions::default().set_sort_attributes(true)),
+///            "<a b=\"2\" z=\"1\"/>");
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct WriterOptions {
+    /// Attributes are printed sorted by name, instead of the order they were read or set.
+    /// Tags kept by `preserve_formatting` are printed as they were read anyway.
+    pub sort_attributes: bool,
+}
+
+impl WriterOptions {
+    pub fn set_sort_attributes(mut self, value: bool) -> WriterOptions {
+        self.sort_attributes = value;
+        self
+    }
+}
@@ -13,12 +13,18 @@
 // specific language governing permissions and limitations
 // under the License.
 
+// The first test predates clippy and is kept as it is.
+#![allow(clippy::bool_assert_comparison, clippy::match_ref_pats, clippy::assertions_on_constants)]
+
 extern crate libsgl_xml;
 
+use std::fs::File;
+use std::io::Read;
 use std::path::Path;
 
 use libsgl_xml::XmlItem;
 use libsgl_xml::XmlDom;
+use libsgl_xml::ParserOptions;
 
 #[test]
 fn open() {
@@ -31,3 +37,29 @@ fn open() {
         &mut Err(_) => assert!(false),
     }
 }
+
+#[test]
+fn parse_reader() {
+    let file = File::open(Path::new("./tests/test.xml")).unwrap();
+    let from_reader = XmlDom::parse_reader(file).unwrap();
+    let from_file = XmlDom::open(Path::new("./tests/test.xml")).unwrap();
+    assert_eq!(XmlItem::as_string(from_reader), XmlItem::as_string(from_file));
+}
+
+#[test]
+fn preserve_formatting() {
+    let mut text = String::new();
+    File::open(Path::new("./tests/test.xml")).unwrap().read_to_string(&mut text).unwrap();
+    let options = ParserOptions::default().set_preserve_formatting(true);
+    let root = XmlDom::open_with(Path::new("./tests/test.xml"), &options).unwrap();
+    assert_eq!(XmlItem::as_string(root.clone()), text);
+
+    // Only the changed tag is printed anew.
+    let node = XmlItem::get_nodes(root.clone())
+                   .into_iter()
+                   .find(|node| XmlItem::get_name(node.clone()) == "node_1.2")
+                   .unwrap();
+    XmlItem::set_attribute(node, "x".to_string(), "1".to_string());
+    assert_eq!(XmlItem::as_string(root.clone()),
+               text.replace("<node_1.2>", "<node_1.2 x=\"1\">"));
+}